@@ -0,0 +1,326 @@
+use std::fs;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use super::error::EmuError;
+use super::varvara::{read_short, Device};
+
+/// Sample frames per second of rendered audio
+pub const SAMPLE_RATE: u32 = 44100;
+
+/// Sample frames rendered each screen frame, at 60 frames per second
+pub const FRAME_SAMPLES: usize = SAMPLE_RATE as usize / 60;
+
+/// Period at which a sample plays back at its own rate
+const NOTE_PERIOD: u32 = SAMPLE_RATE * 0x4000 / 11025;
+
+/// Length of one step of an ADSR nibble, a fifteenth of a second
+const ADSR_STEP: u32 = SAMPLE_RATE / 0xf;
+
+/// How fast to step through a sample for each note of the top octave
+const ADVANCES: [u32; 12] = [
+    0x80000, 0x879c8, 0x8facd, 0x9837f, 0xa1451, 0xaadc1, 0xb504f, 0xbfc88, 0xcb2ff, 0xd7450,
+    0xe411f, 0xf1a1c,
+];
+
+/// One of the four Audio devices. Writing the pitch port copies the
+/// sample out of main memory and starts playing it; `render` then mixes
+/// it into a buffer a chunk at a time.
+#[derive(Default)]
+pub struct Audio {
+    sample: Vec<u8>,
+    /// Position in the sample, as a fraction of `period`
+    count: u32,
+    /// How far `count` moves each sample frame, zero when silent
+    advance: u32,
+    period: u32,
+    /// Sample frames since the note started
+    age: u32,
+    /// Ages at which the attack, decay, sustain and release end
+    a: u32,
+    d: u32,
+    s: u32,
+    r: u32,
+    i: usize,
+    volume: [i32; 2],
+    repeat: bool,
+}
+
+impl Audio {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.advance != 0 && self.period != 0
+    }
+
+    /// Start the note described by the ports at `base`
+    fn start(&mut self, io: &[u8], main: &[u8], base: u8) {
+        let pitch = io[(base | 0xf) as usize] & 0x7f;
+        let adsr = read_short(io, base | 0x8) as u32;
+        let addr = read_short(io, base | 0xc) as usize;
+        let len = (read_short(io, base | 0xa) as usize).min(main.len() - addr);
+        let volume = io[(base | 0xe) as usize];
+
+        self.sample = main[addr..addr + len].to_vec();
+        self.volume = [(volume >> 4) as i32, (volume & 0xf) as i32];
+        self.repeat = io[(base | 0xf) as usize] & 0x80 == 0;
+        if pitch >= 108 || len == 0 {
+            self.advance = 0;
+            return;
+        }
+        self.advance = ADVANCES[pitch as usize % 12] >> (8 - pitch / 12);
+        self.a = ADSR_STEP * (adsr >> 12);
+        self.d = ADSR_STEP * (adsr >> 8 & 0xf) + self.a;
+        self.s = ADSR_STEP * (adsr >> 4 & 0xf) + self.d;
+        self.r = ADSR_STEP * (adsr & 0xf) + self.s;
+        self.age = 0;
+        self.count = 0;
+        self.i = 0;
+        self.period = if len <= 0x100 {
+            // short samples are single waveform cycles, tuned to the pitch
+            NOTE_PERIOD * 337 / 2 / len as u32
+        } else {
+            NOTE_PERIOD
+        };
+    }
+
+    /// Loudness at `age`. Without a release the note holds steady;
+    /// otherwise it goes quiet, and stops, once the release is over.
+    fn envelope(&mut self, age: u32) -> i32 {
+        let (a, d, s, r) = (self.a, self.d, self.s, self.r);
+        if r == 0 {
+            0x0888
+        } else if age < a {
+            (0x0888 * age / a) as i32
+        } else if age < d {
+            (0x0444 * (2 * d - a - age) / (d - a)) as i32
+        } else if age < s {
+            0x0444
+        } else if age < r {
+            (0x0444 * (r - age) / (r - s)) as i32
+        } else {
+            self.advance = 0;
+            0
+        }
+    }
+
+    /// Mix the note into interleaved stereo `samples`. True if it stopped
+    /// during this chunk.
+    pub fn render(&mut self, samples: &mut [i16]) -> bool {
+        if !self.is_playing() {
+            return false;
+        }
+        for frame in samples.chunks_exact_mut(2) {
+            self.count += self.advance;
+            self.i += (self.count / self.period) as usize;
+            self.count %= self.period;
+            if self.i >= self.sample.len() {
+                if !self.repeat {
+                    self.advance = 0;
+                    break;
+                }
+                self.i %= self.sample.len();
+            }
+            let value = self.sample[self.i].wrapping_add(0x80) as i8 as i32;
+            let s = value * self.envelope(self.age);
+            self.age += 1;
+            for (out, volume) in frame.iter_mut().zip(self.volume) {
+                *out = out.saturating_add((s * volume / 0x180) as i16);
+            }
+            if self.advance == 0 {
+                break;
+            }
+        }
+        self.advance == 0
+    }
+
+    /// Current loudness of each side, left in the high nibble
+    fn output(&mut self) -> u8 {
+        if !self.is_playing() {
+            return 0;
+        }
+        let envelope = self.envelope(self.age);
+        let [left, right] = self.volume.map(|volume| match volume {
+            0 => 0,
+            _ => (1 + envelope * volume / 0x800).min(0xf) as u8,
+        });
+        left << 4 | right
+    }
+}
+
+impl Device for Audio {
+    fn dei(&mut self, io: &[u8], addr: u8) -> Result<u8, EmuError> {
+        let port = addr & 0x0F;
+        match port {
+            0x2 => Ok((self.i >> 8) as u8),
+            0x3 => Ok(self.i as u8),
+            0x4 => Ok(self.output()),
+            _ => Ok(io[addr as usize]),
+        }
+    }
+
+    fn dei2(&mut self, io: &[u8], addr: u8) -> Result<u16, EmuError> {
+        let port = addr & 0x0F;
+        match port {
+            0x2 => Ok(self.i as u16),
+            _ => Ok(read_short(io, addr)),
+        }
+    }
+
+    fn deo(&mut self, io: &mut [u8], main: &mut [u8], addr: u8, _byte: u8) -> Result<(), EmuError> {
+        let port = addr & 0x0F;
+        if port == 0xf {
+            self.start(io, main, addr & 0xF0);
+        }
+        Ok(())
+    }
+
+    fn deo2(
+        &mut self,
+        io: &mut [u8],
+        main: &mut [u8],
+        addr: u8,
+        _short: u16,
+    ) -> Result<(), EmuError> {
+        let port = addr & 0x0F;
+        match port {
+            0x0 | 0x8 | 0xa | 0xc => Ok(()),
+            // the low byte lands on the pitch port
+            0xe => {
+                self.start(io, main, addr & 0xF0);
+                Ok(())
+            }
+            _ => Err(EmuError::UnknownPort(addr)),
+        }
+    }
+}
+
+/// Somewhere to send rendered audio: interleaved stereo 16-bit samples at
+/// `SAMPLE_RATE`
+pub trait AudioSink {
+    fn write(&mut self, samples: &[i16]) -> io::Result<()>;
+}
+
+/// Rendering into memory, ie for tests
+impl AudioSink for Vec<i16> {
+    fn write(&mut self, samples: &[i16]) -> io::Result<()> {
+        self.extend_from_slice(samples);
+        Ok(())
+    }
+}
+
+/// Writes audio out as a WAV file. The header is kept up to date after
+/// every write, so the file is playable however the run ends.
+pub struct WavSink<W: Write + Seek> {
+    out: W,
+    data_len: u32,
+}
+
+impl WavSink<BufWriter<fs::File>> {
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::new(BufWriter::new(fs::File::create(path)?))
+    }
+}
+
+impl<W: Write + Seek> WavSink<W> {
+    pub fn new(mut out: W) -> io::Result<Self> {
+        let channels: u16 = 2;
+        let bytes_per_frame = channels * 2;
+        out.write_all(b"RIFF")?;
+        out.write_all(&36u32.to_le_bytes())?;
+        out.write_all(b"WAVEfmt ")?;
+        out.write_all(&16u32.to_le_bytes())?;
+        // integer PCM
+        out.write_all(&1u16.to_le_bytes())?;
+        out.write_all(&channels.to_le_bytes())?;
+        out.write_all(&SAMPLE_RATE.to_le_bytes())?;
+        out.write_all(&(SAMPLE_RATE * bytes_per_frame as u32).to_le_bytes())?;
+        out.write_all(&bytes_per_frame.to_le_bytes())?;
+        out.write_all(&16u16.to_le_bytes())?;
+        out.write_all(b"data")?;
+        out.write_all(&0u32.to_le_bytes())?;
+        out.flush()?;
+        Ok(Self { out, data_len: 0 })
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+impl<W: Write + Seek> AudioSink for WavSink<W> {
+    fn write(&mut self, samples: &[i16]) -> io::Result<()> {
+        let bytes: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+        self.out.write_all(&bytes)?;
+        self.data_len += bytes.len() as u32;
+        self.out.seek(SeekFrom::Start(4))?;
+        self.out.write_all(&(36 + self.data_len).to_le_bytes())?;
+        self.out.seek(SeekFrom::Start(40))?;
+        self.out.write_all(&self.data_len.to_le_bytes())?;
+        self.out.seek(SeekFrom::End(0))?;
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::Cpu;
+    use crate::varvara::Varvara;
+
+    /// Set up Audio0 to play `len` bytes of 0xff at 0x1000, left side only
+    fn play(varvara: &mut Varvara, len: u16, pitch: u8) {
+        varvara.main[0x1000..0x1000 + len as usize].fill(0xff);
+        varvara.deo2(0x3a, len).unwrap();
+        varvara.deo2(0x3c, 0x1000).unwrap();
+        varvara.deo(0x3e, 0xf0).unwrap();
+        varvara.deo(0x3f, pitch).unwrap();
+    }
+
+    #[test]
+    fn note_plays_on_its_side() {
+        let mut varvara = Varvara::new();
+        let mut cpu = Cpu::new();
+        play(&mut varvara, 0x200, 60);
+        assert_eq!(varvara.dei(0x34), Ok(0xf0));
+
+        let mut pcm = Vec::new();
+        varvara.render_audio(&mut cpu, 4, &mut pcm).unwrap();
+        assert_eq!(pcm, [10834, 0, 10834, 0, 10834, 0, 10834, 0]);
+        assert_eq!(varvara.dei2(0x32), Ok(4));
+    }
+
+    #[test]
+    fn one_shot_fires_vector_when_done() {
+        let mut varvara = Varvara::new();
+        let mut cpu = Cpu::new();
+        // @on-done #01 #00 STZ BRK
+        varvara.main[0x0200..0x0206].copy_from_slice(&[0x80, 0x01, 0x80, 0x00, 0x11, 0x00]);
+        varvara.deo2(0x30, 0x0200).unwrap();
+        play(&mut varvara, 0x200, 0x80 | 60);
+
+        let mut pcm = Vec::new();
+        varvara.render_audio(&mut cpu, 0x100, &mut pcm).unwrap();
+        assert_eq!(varvara.main[0x00], 0x00);
+        varvara.render_audio(&mut cpu, 0x200, &mut pcm).unwrap();
+        assert_eq!(varvara.main[0x00], 0x01);
+        assert_eq!(varvara.dei(0x34), Ok(0x00));
+        assert_eq!(pcm.len(), 0x300 * 2);
+    }
+
+    #[test]
+    fn wav_header_tracks_data() {
+        let mut wav = WavSink::new(io::Cursor::new(Vec::new())).unwrap();
+        wav.write(&[1, -1, 2, -2]).unwrap();
+        let bytes = wav.into_inner().into_inner();
+
+        assert_eq!(bytes.len(), 44 + 8);
+        assert_eq!(&bytes[0..4], b"RIFF");
+        assert_eq!(bytes[4..8], (36u32 + 8).to_le_bytes());
+        assert_eq!(bytes[24..28], SAMPLE_RATE.to_le_bytes());
+        assert_eq!(bytes[40..44], 8u32.to_le_bytes());
+        assert_eq!(bytes[44..46], 1i16.to_le_bytes());
+    }
+}
@@ -1,41 +1,186 @@
-use std::io;
-use std::io::prelude::*;
+use std::collections::VecDeque;
+use std::io::{self, prelude::*, Stderr, Stdout};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
 
+use super::error::EmuError;
 use super::varvara::Device;
 
-pub struct Console {
-    out: std::io::Stdout,
+/// What the byte in Console/read is, as reported by Console/type
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[repr(u8)]
+pub enum InputType {
+    Stdin = 1,
+    Argument = 2,
+    /// The newline after each argument but the last
+    ArgumentSpacer = 3,
+    /// The newline after the last argument, and the 0 sent when stdin ends
+    ArgumentEnd = 4,
+}
+
+/// Read `input` on its own thread, handing over bytes as they arrive, so a
+/// frontend can poll for them without blocking
+pub fn spawn_reader(input: impl Read + Send + 'static) -> Receiver<u8> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for byte in io::BufReader::new(input).bytes() {
+            let Ok(byte) = byte else { break };
+            if sender.send(byte).is_err() {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+/// The Console device, writing the ROM's output to `out` and its errors to
+/// `err`. Tests can swap stdout and stderr for `Vec<u8>`s to capture them.
+pub struct Console<O: Write = Stdout, E: Write = Stderr> {
+    out: O,
+    err: E,
+    /// Input waiting to go to the ROM once its reset vector is done
+    queue: VecDeque<(u8, InputType)>,
 }
 
 impl Console {
     pub fn new() -> Self {
-        let out = std::io::stdout();
-        Self { out }
+        Self::with_sinks(io::stdout(), io::stderr())
+    }
+}
+
+impl<O: Write, E: Write> Console<O, E> {
+    pub fn with_sinks(out: O, err: E) -> Self {
+        Self {
+            out,
+            err,
+            queue: VecDeque::new(),
+        }
+    }
+
+    /// Where Console/write goes
+    pub fn out(&self) -> &O {
+        &self.out
+    }
+
+    /// Where Console/error goes
+    pub fn err(&self) -> &E {
+        &self.err
+    }
+
+    /// Queue command-line arguments for the ROM. Each one comes through
+    /// byte by byte, followed by a newline marking a spacer or the end.
+    pub fn queue_args<S: AsRef<str>>(&mut self, args: impl IntoIterator<Item = S>) {
+        let args: Vec<S> = args.into_iter().collect();
+        for (i, arg) in args.iter().enumerate() {
+            for &byte in arg.as_ref().as_bytes() {
+                self.queue.push_back((byte, InputType::Argument));
+            }
+            let end = if i + 1 == args.len() {
+                InputType::ArgumentEnd
+            } else {
+                InputType::ArgumentSpacer
+            };
+            self.queue.push_back((b'\n', end));
+        }
+    }
+
+    pub fn has_queued_input(&self) -> bool {
+        !self.queue.is_empty()
     }
 
-    fn write(&mut self, byte: u8) -> io::Result<()> {
-        self.out.write_all(&[byte])?;
-        self.out.flush()?;
-        Ok(())
+    /// Take the next byte of queued input
+    pub fn next_input(&mut self) -> Option<(u8, InputType)> {
+        self.queue.pop_front()
     }
 }
 
+fn write(sink: &mut impl Write, byte: u8) -> io::Result<()> {
+    sink.write_all(&[byte])?;
+    sink.flush()?;
+    Ok(())
+}
+
 impl Default for Console {
     fn default() -> Self {
         Self::new()
     }
 }
 
-impl Device for Console {
-    fn notify_deo(&mut self, _io: &[u8], _main: &[u8], addr: u8, byte: u8) {
+impl<O: Write + 'static, E: Write + 'static> Device for Console<O, E> {
+    fn deo(
+        &mut self,
+        _io: &mut [u8],
+        _main: &mut [u8],
+        addr: u8,
+        byte: u8,
+    ) -> Result<(), EmuError> {
         let port = addr & 0x0F;
-        let _ = match port {
-            0x8 => self.write(byte),
-            _ => panic!("Don't know how to write to port {port}!"),
-        };
+        match port {
+            0x8 => {
+                let _ = write(&mut self.out, byte);
+                Ok(())
+            }
+            0x9 => {
+                let _ = write(&mut self.err, byte);
+                Ok(())
+            }
+            _ => Err(EmuError::UnknownPort(addr)),
+        }
+    }
+
+    fn deo2(
+        &mut self,
+        _io: &mut [u8],
+        _main: &mut [u8],
+        addr: u8,
+        _short: u16,
+    ) -> Result<(), EmuError> {
+        let port = addr & 0x0F;
+        match port {
+            0x0 => Ok(()),
+            _ => Err(EmuError::UnknownPort(addr)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::Cpu;
+    use crate::varvara::Varvara;
+
+    type Captured = Console<Vec<u8>, Vec<u8>>;
+
+    /// Run `rom` with its console output captured
+    fn run_captured(rom: &[u8]) -> Varvara {
+        let mut varvara = Varvara::new();
+        let mut cpu = Cpu::new();
+        varvara.register(0x1, Box::new(Captured::with_sinks(vec![], vec![])));
+        varvara.main[0x0100..0x0100 + rom.len()].copy_from_slice(rom);
+        varvara.eval(&mut cpu, 0x0100).unwrap();
+        varvara
+    }
+
+    #[test]
+    fn hello_rom_output() {
+        let varvara = run_captured(include_bytes!("../roms/test/hello.rom"));
+        let console = varvara.device::<Captured>().unwrap();
+        assert_eq!(console.out(), b"h\n");
+        assert!(console.err().is_empty());
+    }
+
+    #[test]
+    fn hi_rom_output() {
+        let varvara = run_captured(include_bytes!("../roms/test/hi.rom"));
+        assert_eq!(varvara.device::<Captured>().unwrap().out(), b"hi\n");
     }
 
-    fn notify_deo2(&mut self, _io: &[u8], _main: &[u8], _addr: u8, _short: u16) {
-        panic!("You can't write a short to the console.");
+    #[test]
+    fn error_port_writes_to_err() {
+        // #21 .Console/error DEO
+        let varvara = run_captured(&[0x80, 0x21, 0x80, 0x19, 0x17]);
+        let console = varvara.device::<Captured>().unwrap();
+        assert!(console.out().is_empty());
+        assert_eq!(console.err(), b"!");
     }
 }
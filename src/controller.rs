@@ -0,0 +1,104 @@
+use super::error::EmuError;
+use super::varvara::Device;
+
+/// Buttons on the Controller device
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Button {
+    A,
+    B,
+    Select,
+    Start,
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl Button {
+    /// Bit of the button in Controller/button
+    pub fn mask(self) -> u8 {
+        match self {
+            Button::A => 0x01,
+            Button::B => 0x02,
+            Button::Select => 0x04,
+            Button::Start => 0x08,
+            Button::Up => 0x10,
+            Button::Down => 0x20,
+            Button::Left => 0x40,
+            Button::Right => 0x80,
+        }
+    }
+}
+
+/// The Controller device. It keeps the held buttons and the last typed key,
+/// which frontend events update through `Varvara::handle`, so the ROM only
+/// ever writes the vector.
+#[derive(Default)]
+pub struct Controller {
+    buttons: u8,
+    key: u8,
+}
+
+impl Controller {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn press(&mut self, button: Button) {
+        self.buttons |= button.mask();
+    }
+
+    pub fn release(&mut self, button: Button) {
+        self.buttons &= !button.mask();
+    }
+
+    /// Set the key read from Controller/key, or 0 once it's been handled
+    pub fn type_key(&mut self, key: u8) {
+        self.key = key;
+    }
+}
+
+impl Device for Controller {
+    fn dei(&mut self, io: &[u8], addr: u8) -> Result<u8, EmuError> {
+        let port = addr & 0x0F;
+        match port {
+            0x2 => Ok(self.buttons),
+            0x3 => Ok(self.key),
+            _ => Ok(io[addr as usize]),
+        }
+    }
+
+    fn dei2(&mut self, io: &[u8], addr: u8) -> Result<u16, EmuError> {
+        let high = self.dei(io, addr)?;
+        let low = self.dei(io, addr.wrapping_add(1))?;
+        Ok(u16::from_be_bytes([high, low]))
+    }
+
+    fn deo(
+        &mut self,
+        _io: &mut [u8],
+        _main: &mut [u8],
+        addr: u8,
+        _byte: u8,
+    ) -> Result<(), EmuError> {
+        let port = addr & 0x0F;
+        match port {
+            0x0..0x4 => Ok(()),
+            _ => Err(EmuError::UnknownPort(addr)),
+        }
+    }
+
+    fn deo2(
+        &mut self,
+        _io: &mut [u8],
+        _main: &mut [u8],
+        addr: u8,
+        _short: u16,
+    ) -> Result<(), EmuError> {
+        let port = addr & 0x0F;
+        match port {
+            0x0 => Ok(()),
+            _ => Err(EmuError::UnknownPort(addr)),
+        }
+    }
+}
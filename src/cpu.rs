@@ -1,39 +1,155 @@
-use super::opcode::{parse_code, Code, CodeFlags, LitFlags};
-use super::varvara::Varvara;
+use std::fmt;
+
+use super::error::EmuError;
+use super::opcode::{name_code, parse_code, Code, CodeFlags, LitFlags};
+use super::varvara::{read_main_short, read_short, write_main_short, Varvara};
+
+/// Faults that halt the CPU, numbered by their System error code
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fault {
+    Underflow = 0x01,
+    Overflow = 0x02,
+    DivisionByZero = 0x03,
+}
+
+impl Fault {
+    /// Error code handed to the System halt vector
+    pub fn code(&self) -> u8 {
+        *self as u8
+    }
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Fault::Underflow => write!(f, "underflow"),
+            Fault::Overflow => write!(f, "overflow"),
+            Fault::DivisionByZero => write!(f, "division by zero"),
+        }
+    }
+}
+
+/// A fault along with the instruction that caused it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Halt {
+    pub fault: Fault,
+    /// Raw byte of the faulting instruction
+    pub instr: u8,
+    /// Address of the faulting instruction
+    pub addr: u16,
+}
+
+impl fmt::Display for Halt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let stack = if self.instr & 0b010_00000 != 0 {
+            "Return-stack"
+        } else {
+            "Working-stack"
+        };
+        write!(
+            f,
+            "{stack} {}, by {} at 0x{:04x}.",
+            self.fault,
+            name_code(self.instr),
+            self.addr
+        )
+    }
+}
+
+/// Why a single operation stopped, before the CPU knows where it happened
+#[derive(Debug)]
+pub enum OpError {
+    Fault(Fault),
+    Device(EmuError),
+}
+
+impl From<Fault> for OpError {
+    fn from(fault: Fault) -> Self {
+        OpError::Fault(fault)
+    }
+}
+
+impl From<EmuError> for OpError {
+    fn from(error: EmuError) -> Self {
+        OpError::Device(error)
+    }
+}
 
 pub struct Stack {
-    bytes: Vec<u8>,
+    bytes: [u8; 0x100],
+    /// Number of bytes on the stack
+    ptr: u8,
+    /// Read position while in keep mode. Pops walk this down instead of
+    /// the stack pointer, so pushes land on top of the untouched operands.
+    keep: Option<u8>,
 }
 
 impl Stack {
     pub fn new() -> Self {
         Self {
-            bytes: Vec::with_capacity(0xFF),
+            bytes: [0; 0x100],
+            ptr: 0,
+            keep: None,
         }
     }
 
-    pub fn peek_mut(&mut self) -> &mut u8 {
-        self.bytes.last_mut().unwrap()
+    /// Bytes on the stack, bottom first
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes[..self.ptr as usize]
     }
 
-    pub fn pop(&mut self) -> u8 {
-        self.bytes.pop().unwrap()
+    /// The top eight bytes the way System/debug prints them, with `|` after
+    /// the bottom of the stack and `<` at the top, ie `00 00 00 00 00 00|01 02 <`
+    pub fn inspect(&self) -> String {
+        let mut line = String::new();
+        let mut i = self.ptr.wrapping_sub(8);
+        while i != self.ptr {
+            let separator = if i == 0xff { '|' } else { ' ' };
+            line.push_str(&format!("{:02x}{separator}", self.bytes[i as usize]));
+            i = i.wrapping_add(1);
+        }
+        line.push('<');
+        line
     }
 
-    pub fn pop2(&mut self) -> u16 {
-        let low_byte = self.pop();
-        let high_byte = self.pop();
-        u16::from_be_bytes([high_byte, low_byte])
+    /// Start reading operands without consuming them
+    pub fn keep(&mut self) {
+        self.keep = Some(self.ptr);
     }
 
-    pub fn push(&mut self, byte: u8) {
-        self.bytes.push(byte);
+    /// Go back to consuming operands
+    pub fn release(&mut self) {
+        self.keep = None;
+    }
+
+    pub fn pop(&mut self) -> Result<u8, Fault> {
+        let ptr = self.keep.as_mut().unwrap_or(&mut self.ptr);
+        if *ptr == 0 {
+            return Err(Fault::Underflow);
+        }
+        *ptr -= 1;
+        Ok(self.bytes[*ptr as usize])
     }
 
-    pub fn push2(&mut self, short: u16) {
+    pub fn pop2(&mut self) -> Result<u16, Fault> {
+        let low_byte = self.pop()?;
+        let high_byte = self.pop()?;
+        Ok(u16::from_be_bytes([high_byte, low_byte]))
+    }
+
+    pub fn push(&mut self, byte: u8) -> Result<(), Fault> {
+        if self.ptr == 0xff {
+            return Err(Fault::Overflow);
+        }
+        self.bytes[self.ptr as usize] = byte;
+        self.ptr += 1;
+        Ok(())
+    }
+
+    pub fn push2(&mut self, short: u16) -> Result<(), Fault> {
         let [high, low] = short.to_be_bytes();
-        self.push(high);
-        self.push(low);
+        self.push(high)?;
+        self.push(low)
     }
 }
 
@@ -72,90 +188,595 @@ impl Cpu {
         u16::from_be_bytes([high_byte, low_byte])
     }
 
+    /// The stack an operation works on, followed by the other stack. Return
+    /// mode swaps the two.
+    fn stacks(&mut self, ret: bool) -> (&mut Stack, &mut Stack) {
+        if ret {
+            (&mut self.ret, &mut self.work)
+        } else {
+            (&mut self.work, &mut self.ret)
+        }
+    }
+
+    /// The stack an operation works on
+    fn stack(&mut self, ret: bool) -> &mut Stack {
+        self.stacks(ret).0
+    }
+
     /// Do one operation
-    pub fn step(&mut self, varvara: &mut Varvara) -> bool {
+    pub fn step(&mut self, varvara: &mut Varvara) -> Result<bool, EmuError> {
+        let addr = self.counter;
         let raw_code = self.next_byte(varvara);
-        let code = parse_code(raw_code);
-        match code {
+        let code = parse_code(raw_code)?;
+        if let Some(f) = code.flags() {
+            if f.keep {
+                self.stack(f.ret).keep();
+            }
+        }
+        let terminate = matches!(code, Code::BRK);
+        let result = match code {
             Code::INC(f) => self.inc(f),
+            Code::POP(f) => self.pop(f),
+            Code::NIP(f) => self.nip(f),
+            Code::SWP(f) => self.swp(f),
+            Code::ROT(f) => self.rot(f),
+            Code::DUP(f) => self.dup(f),
+            Code::OVR(f) => self.ovr(f),
+            Code::EQU(f) => self.equ(f),
+            Code::NEQ(f) => self.neq(f),
+            Code::GTH(f) => self.gth(f),
+            Code::LTH(f) => self.lth(f),
+            Code::JMP(f) => self.jmp(f),
+            Code::JCN(f) => self.jcn(f),
+            Code::JSR(f) => self.jsr(f),
+            Code::STH(f) => self.sth(f),
+            Code::LDZ(f) => self.ldz(f, varvara),
+            Code::STZ(f) => self.stz(f, varvara),
+            Code::LDR(f) => self.ldr(f, varvara),
+            Code::STR(f) => self.str(f, varvara),
+            Code::LDA(f) => self.lda(f, varvara),
+            Code::STA(f) => self.sta(f, varvara),
             Code::ADD(f) => self.add(f),
             Code::SUB(f) => self.sub(f),
+            Code::MUL(f) => self.mul(f),
+            Code::DIV(f) => self.div(f),
+            Code::AND(f) => self.and(f),
+            Code::ORA(f) => self.ora(f),
+            Code::EOR(f) => self.eor(f),
+            Code::SFT(f) => self.sft(f),
             Code::LIT(f) => self.lit(f, varvara),
+            Code::JCI => self.jci(varvara),
+            Code::JMI => self.jmi(varvara),
+            Code::JSI => self.jsi(varvara),
             Code::DEO(f) => self.deo(f, varvara),
             Code::DEI(f) => self.dei(f, varvara),
-            Code::BRK => return true,
+            Code::BRK => Ok(()),
+        };
+        self.work.release();
+        self.ret.release();
+        match result {
+            Ok(()) => Ok(terminate),
+            Err(OpError::Fault(fault)) => self.fault(
+                Halt {
+                    fault,
+                    instr: raw_code,
+                    addr,
+                },
+                varvara,
+            ),
+            Err(OpError::Device(error)) => Err(error),
+        }
+    }
+
+    /// Hand a fault to the ROM's System/vector, with the working stack
+    /// replaced by `addr* instr code`. Without a vector the machine halts.
+    fn fault(&mut self, halt: Halt, varvara: &Varvara) -> Result<bool, EmuError> {
+        let handler = read_short(&varvara.io, 0x00);
+        if handler == 0 {
+            return Err(EmuError::StackFault(halt));
+        }
+        let [high, low] = halt.addr.to_be_bytes();
+        self.work = Stack::new();
+        self.work.bytes[..4].copy_from_slice(&[high, low, halt.instr, halt.fault.code()]);
+        self.work.ptr = 4;
+        self.counter = handler;
+        Ok(false)
+    }
+
+    /// Move the instruction pointer, relatively for a byte or absolutely
+    /// for a short
+    fn jump(&mut self, f: &CodeFlags) -> Result<(), OpError> {
+        self.counter = if f.short {
+            self.stack(f.ret).pop2()?
+        } else {
+            self.relative_addr(f)?
+        };
+        Ok(())
+    }
+
+    /// Read a byte or short from main memory onto the stack
+    fn load(&mut self, f: &CodeFlags, addr: u16, varvara: &Varvara) -> Result<(), OpError> {
+        let stack = self.stack(f.ret);
+        if f.short {
+            stack.push2(read_main_short(&varvara.main, addr))?;
+        } else {
+            stack.push(varvara.main[addr as usize])?;
+        }
+        Ok(())
+    }
+
+    /// Write a byte or short from the stack into main memory
+    fn store(&mut self, f: &CodeFlags, addr: u16, varvara: &mut Varvara) -> Result<(), OpError> {
+        let stack = self.stack(f.ret);
+        if f.short {
+            write_main_short(&mut varvara.main, addr, stack.pop2()?);
+        } else {
+            varvara.main[addr as usize] = stack.pop()?;
         }
-        false
+        Ok(())
+    }
+
+    /// Pop a relative address byte and resolve it against the instruction
+    /// pointer
+    fn relative_addr(&mut self, f: &CodeFlags) -> Result<u16, OpError> {
+        let offset = self.stack(f.ret).pop()? as i8;
+        Ok(self.counter.wrapping_add_signed(offset as i16))
     }
 
     /// Execute INC
-    pub fn inc(&mut self, f: CodeFlags) {
+    pub fn inc(&mut self, f: CodeFlags) -> Result<(), OpError> {
+        let stack = self.stack(f.ret);
+        if f.short {
+            let a = stack.pop2()?;
+            stack.push2(a.wrapping_add(1))?;
+        } else {
+            let a = stack.pop()?;
+            stack.push(a.wrapping_add(1))?;
+        }
+        Ok(())
+    }
+
+    /// Execute POP
+    pub fn pop(&mut self, f: CodeFlags) -> Result<(), OpError> {
+        let stack = self.stack(f.ret);
         if f.short {
-            let a = self.work.pop2();
-            self.work.push2(a.wrapping_add(1));
+            stack.pop2()?;
         } else {
-            let top = self.work.peek_mut();
-            *top = top.wrapping_add(1);
+            stack.pop()?;
         }
+        Ok(())
+    }
+
+    /// Execute NIP
+    pub fn nip(&mut self, f: CodeFlags) -> Result<(), OpError> {
+        let stack = self.stack(f.ret);
+        if f.short {
+            let b = stack.pop2()?;
+            stack.pop2()?;
+            stack.push2(b)?;
+        } else {
+            let b = stack.pop()?;
+            stack.pop()?;
+            stack.push(b)?;
+        }
+        Ok(())
+    }
+
+    /// Execute SWP
+    pub fn swp(&mut self, f: CodeFlags) -> Result<(), OpError> {
+        let stack = self.stack(f.ret);
+        if f.short {
+            let b = stack.pop2()?;
+            let a = stack.pop2()?;
+            stack.push2(b)?;
+            stack.push2(a)?;
+        } else {
+            let b = stack.pop()?;
+            let a = stack.pop()?;
+            stack.push(b)?;
+            stack.push(a)?;
+        }
+        Ok(())
+    }
+
+    /// Execute ROT
+    pub fn rot(&mut self, f: CodeFlags) -> Result<(), OpError> {
+        let stack = self.stack(f.ret);
+        if f.short {
+            let c = stack.pop2()?;
+            let b = stack.pop2()?;
+            let a = stack.pop2()?;
+            stack.push2(b)?;
+            stack.push2(c)?;
+            stack.push2(a)?;
+        } else {
+            let c = stack.pop()?;
+            let b = stack.pop()?;
+            let a = stack.pop()?;
+            stack.push(b)?;
+            stack.push(c)?;
+            stack.push(a)?;
+        }
+        Ok(())
+    }
+
+    /// Execute DUP
+    pub fn dup(&mut self, f: CodeFlags) -> Result<(), OpError> {
+        let stack = self.stack(f.ret);
+        if f.short {
+            let a = stack.pop2()?;
+            stack.push2(a)?;
+            stack.push2(a)?;
+        } else {
+            let a = stack.pop()?;
+            stack.push(a)?;
+            stack.push(a)?;
+        }
+        Ok(())
+    }
+
+    /// Execute OVR
+    pub fn ovr(&mut self, f: CodeFlags) -> Result<(), OpError> {
+        let stack = self.stack(f.ret);
+        if f.short {
+            let b = stack.pop2()?;
+            let a = stack.pop2()?;
+            stack.push2(a)?;
+            stack.push2(b)?;
+            stack.push2(a)?;
+        } else {
+            let b = stack.pop()?;
+            let a = stack.pop()?;
+            stack.push(a)?;
+            stack.push(b)?;
+            stack.push(a)?;
+        }
+        Ok(())
+    }
+
+    /// Execute EQU
+    pub fn equ(&mut self, f: CodeFlags) -> Result<(), OpError> {
+        let stack = self.stack(f.ret);
+        let result = if f.short {
+            let b = stack.pop2()?;
+            let a = stack.pop2()?;
+            a == b
+        } else {
+            let b = stack.pop()?;
+            let a = stack.pop()?;
+            a == b
+        };
+        stack.push(result as u8)?;
+        Ok(())
+    }
+
+    /// Execute NEQ
+    pub fn neq(&mut self, f: CodeFlags) -> Result<(), OpError> {
+        let stack = self.stack(f.ret);
+        let result = if f.short {
+            let b = stack.pop2()?;
+            let a = stack.pop2()?;
+            a != b
+        } else {
+            let b = stack.pop()?;
+            let a = stack.pop()?;
+            a != b
+        };
+        stack.push(result as u8)?;
+        Ok(())
+    }
+
+    /// Execute GTH
+    pub fn gth(&mut self, f: CodeFlags) -> Result<(), OpError> {
+        let stack = self.stack(f.ret);
+        let result = if f.short {
+            let b = stack.pop2()?;
+            let a = stack.pop2()?;
+            a > b
+        } else {
+            let b = stack.pop()?;
+            let a = stack.pop()?;
+            a > b
+        };
+        stack.push(result as u8)?;
+        Ok(())
+    }
+
+    /// Execute LTH
+    pub fn lth(&mut self, f: CodeFlags) -> Result<(), OpError> {
+        let stack = self.stack(f.ret);
+        let result = if f.short {
+            let b = stack.pop2()?;
+            let a = stack.pop2()?;
+            a < b
+        } else {
+            let b = stack.pop()?;
+            let a = stack.pop()?;
+            a < b
+        };
+        stack.push(result as u8)?;
+        Ok(())
+    }
+
+    /// Execute JMP
+    pub fn jmp(&mut self, f: CodeFlags) -> Result<(), OpError> {
+        self.jump(&f)?;
+        Ok(())
+    }
+
+    /// Execute JCN
+    pub fn jcn(&mut self, f: CodeFlags) -> Result<(), OpError> {
+        let addr = if f.short {
+            self.stack(f.ret).pop2()?
+        } else {
+            self.relative_addr(&f)?
+        };
+        let condition = self.stack(f.ret).pop()?;
+        if condition != 0 {
+            self.counter = addr;
+        }
+        Ok(())
+    }
+
+    /// Execute JSR
+    pub fn jsr(&mut self, f: CodeFlags) -> Result<(), OpError> {
+        let counter = self.counter;
+        self.jump(&f)?;
+        let (_, other) = self.stacks(f.ret);
+        other.push2(counter)?;
+        Ok(())
+    }
+
+    /// Execute STH
+    pub fn sth(&mut self, f: CodeFlags) -> Result<(), OpError> {
+        let (stack, other) = self.stacks(f.ret);
+        if f.short {
+            let a = stack.pop2()?;
+            other.push2(a)?;
+        } else {
+            let a = stack.pop()?;
+            other.push(a)?;
+        }
+        Ok(())
+    }
+
+    /// Execute LDZ
+    pub fn ldz(&mut self, f: CodeFlags, varvara: &Varvara) -> Result<(), OpError> {
+        let stack = self.stack(f.ret);
+        let addr = stack.pop()?;
+        self.load(&f, addr as u16, varvara)?;
+        Ok(())
+    }
+
+    /// Execute STZ
+    pub fn stz(&mut self, f: CodeFlags, varvara: &mut Varvara) -> Result<(), OpError> {
+        let stack = self.stack(f.ret);
+        let addr = stack.pop()?;
+        self.store(&f, addr as u16, varvara)?;
+        Ok(())
+    }
+
+    /// Execute LDR
+    pub fn ldr(&mut self, f: CodeFlags, varvara: &Varvara) -> Result<(), OpError> {
+        let addr = self.relative_addr(&f)?;
+        self.load(&f, addr, varvara)?;
+        Ok(())
+    }
+
+    /// Execute STR
+    pub fn str(&mut self, f: CodeFlags, varvara: &mut Varvara) -> Result<(), OpError> {
+        let addr = self.relative_addr(&f)?;
+        self.store(&f, addr, varvara)?;
+        Ok(())
+    }
+
+    /// Execute LDA
+    pub fn lda(&mut self, f: CodeFlags, varvara: &Varvara) -> Result<(), OpError> {
+        let stack = self.stack(f.ret);
+        let addr = stack.pop2()?;
+        self.load(&f, addr, varvara)?;
+        Ok(())
+    }
+
+    /// Execute STA
+    pub fn sta(&mut self, f: CodeFlags, varvara: &mut Varvara) -> Result<(), OpError> {
+        let stack = self.stack(f.ret);
+        let addr = stack.pop2()?;
+        self.store(&f, addr, varvara)?;
+        Ok(())
     }
 
     /// Execute ADD
-    pub fn add(&mut self, f: CodeFlags) {
+    pub fn add(&mut self, f: CodeFlags) -> Result<(), OpError> {
+        let stack = self.stack(f.ret);
         if f.short {
-            let b = self.work.pop2();
-            let a = self.work.pop2();
-            self.work.push2(a.wrapping_add(b));
+            let b = stack.pop2()?;
+            let a = stack.pop2()?;
+            stack.push2(a.wrapping_add(b))?;
         } else {
-            let b = self.work.pop();
-            let a = self.work.pop();
-            self.work.push(a.wrapping_add(b));
+            let b = stack.pop()?;
+            let a = stack.pop()?;
+            stack.push(a.wrapping_add(b))?;
         }
+        Ok(())
     }
 
     /// Execute SUB
-    pub fn sub(&mut self, f: CodeFlags) {
+    pub fn sub(&mut self, f: CodeFlags) -> Result<(), OpError> {
+        let stack = self.stack(f.ret);
+        if f.short {
+            let b = stack.pop2()?;
+            let a = stack.pop2()?;
+            stack.push2(a.wrapping_sub(b))?;
+        } else {
+            let b = stack.pop()?;
+            let a = stack.pop()?;
+            stack.push(a.wrapping_sub(b))?;
+        }
+        Ok(())
+    }
+
+    /// Execute MUL
+    pub fn mul(&mut self, f: CodeFlags) -> Result<(), OpError> {
+        let stack = self.stack(f.ret);
+        if f.short {
+            let b = stack.pop2()?;
+            let a = stack.pop2()?;
+            stack.push2(a.wrapping_mul(b))?;
+        } else {
+            let b = stack.pop()?;
+            let a = stack.pop()?;
+            stack.push(a.wrapping_mul(b))?;
+        }
+        Ok(())
+    }
+
+    /// Execute DIV
+    pub fn div(&mut self, f: CodeFlags) -> Result<(), OpError> {
+        let stack = self.stack(f.ret);
+        if f.short {
+            let b = stack.pop2()?;
+            let a = stack.pop2()?;
+            stack.push2(a.checked_div(b).ok_or(Fault::DivisionByZero)?)?;
+        } else {
+            let b = stack.pop()?;
+            let a = stack.pop()?;
+            stack.push(a.checked_div(b).ok_or(Fault::DivisionByZero)?)?;
+        }
+        Ok(())
+    }
+
+    /// Execute AND
+    pub fn and(&mut self, f: CodeFlags) -> Result<(), OpError> {
+        let stack = self.stack(f.ret);
+        if f.short {
+            let b = stack.pop2()?;
+            let a = stack.pop2()?;
+            stack.push2(a & b)?;
+        } else {
+            let b = stack.pop()?;
+            let a = stack.pop()?;
+            stack.push(a & b)?;
+        }
+        Ok(())
+    }
+
+    /// Execute ORA
+    pub fn ora(&mut self, f: CodeFlags) -> Result<(), OpError> {
+        let stack = self.stack(f.ret);
+        if f.short {
+            let b = stack.pop2()?;
+            let a = stack.pop2()?;
+            stack.push2(a | b)?;
+        } else {
+            let b = stack.pop()?;
+            let a = stack.pop()?;
+            stack.push(a | b)?;
+        }
+        Ok(())
+    }
+
+    /// Execute EOR
+    pub fn eor(&mut self, f: CodeFlags) -> Result<(), OpError> {
+        let stack = self.stack(f.ret);
         if f.short {
-            let b = self.work.pop2();
-            let a = self.work.pop2();
-            self.work.push2(a.wrapping_sub(b));
+            let b = stack.pop2()?;
+            let a = stack.pop2()?;
+            stack.push2(a ^ b)?;
         } else {
-            let b = self.work.pop();
-            let a = self.work.pop();
-            self.work.push(a.wrapping_sub(b));
+            let b = stack.pop()?;
+            let a = stack.pop()?;
+            stack.push(a ^ b)?;
         }
+        Ok(())
+    }
+
+    /// Execute SFT
+    pub fn sft(&mut self, f: CodeFlags) -> Result<(), OpError> {
+        let stack = self.stack(f.ret);
+        // the shift amount is always a byte: low nibble right, high nibble left
+        let shift = stack.pop()?;
+        let right = (shift & 0x0f) as u32;
+        let left = (shift >> 4) as u32;
+        if f.short {
+            let a = stack.pop2()?;
+            let shifted = a.checked_shr(right).unwrap_or(0);
+            stack.push2(shifted.checked_shl(left).unwrap_or(0))?;
+        } else {
+            let a = stack.pop()?;
+            let shifted = a.checked_shr(right).unwrap_or(0);
+            stack.push(shifted.checked_shl(left).unwrap_or(0))?;
+        }
+        Ok(())
     }
 
     /// Execute LIT
-    pub fn lit(&mut self, f: LitFlags, varvara: &Varvara) {
+    pub fn lit(&mut self, f: LitFlags, varvara: &Varvara) -> Result<(), OpError> {
         if f.short {
             let short = self.next_short(varvara);
-            self.work.push2(short);
+            self.stack(f.ret).push2(short)?;
         } else {
             let byte = self.next_byte(varvara);
-            self.work.push(byte);
+            self.stack(f.ret).push(byte)?;
         }
+        Ok(())
+    }
+
+    /// Execute JCI
+    pub fn jci(&mut self, varvara: &Varvara) -> Result<(), OpError> {
+        let offset = self.next_short(varvara);
+        let condition = self.work.pop()?;
+        if condition != 0 {
+            self.counter = self.counter.wrapping_add(offset);
+        }
+        Ok(())
+    }
+
+    /// Execute JMI
+    pub fn jmi(&mut self, varvara: &Varvara) -> Result<(), OpError> {
+        let offset = self.next_short(varvara);
+        self.counter = self.counter.wrapping_add(offset);
+        Ok(())
+    }
+
+    /// Execute JSI
+    pub fn jsi(&mut self, varvara: &Varvara) -> Result<(), OpError> {
+        let offset = self.next_short(varvara);
+        self.ret.push2(self.counter)?;
+        self.counter = self.counter.wrapping_add(offset);
+        Ok(())
     }
 
     /// Execute DEO
-    pub fn deo(&mut self, f: CodeFlags, varvara: &mut Varvara) {
-        let addr = self.work.pop();
+    pub fn deo(&mut self, f: CodeFlags, varvara: &mut Varvara) -> Result<(), OpError> {
+        let stack = self.stack(f.ret);
+        let addr = stack.pop()?;
         if f.short {
-            let short = self.work.pop2();
-            varvara.deo2(addr, short);
+            let short = stack.pop2()?;
+            varvara.deo2(addr, short)?;
         } else {
-            let byte = self.work.pop();
-            varvara.deo(addr, byte);
+            let byte = stack.pop()?;
+            varvara.deo(addr, byte)?;
+        }
+        // the System may have been asked to print the stacks
+        if addr >> 4 == 0x0 {
+            varvara.inspect(self);
         }
+        Ok(())
     }
 
     /// Execute DEI
-    pub fn dei(&mut self, f: CodeFlags, varvara: &mut Varvara) {
-        let addr = self.work.pop();
+    pub fn dei(&mut self, f: CodeFlags, varvara: &mut Varvara) -> Result<(), OpError> {
+        let stack = self.stack(f.ret);
+        let addr = stack.pop()?;
         if f.short {
-            self.work.push2(varvara.dei2(addr));
+            let short = varvara.dei2(addr)?;
+            stack.push2(short)?;
         } else {
-            self.work.push(varvara.dei(addr));
+            let byte = varvara.dei(addr)?;
+            stack.push(byte)?;
         }
+        Ok(())
     }
 }
 
@@ -164,3 +785,657 @@ impl Default for Cpu {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Run a program loaded at 0x0100 until it hits BRK
+    fn run(varvara: &mut Varvara, program: &[u8]) -> Result<Cpu, EmuError> {
+        varvara.main[0x0100..0x0100 + program.len()].copy_from_slice(program);
+        let mut cpu = Cpu::new();
+        while !cpu.step(varvara)? {}
+        Ok(cpu)
+    }
+
+    /// The working stack after running a program on a fresh machine
+    fn work(program: &[u8]) -> Vec<u8> {
+        let cpu = run(&mut Varvara::new(), program).unwrap();
+        cpu.work.bytes().to_vec()
+    }
+
+    #[test]
+    fn inc_byte_and_short() {
+        // #01 INC
+        assert_eq!(work(&[0x80, 0x01, 0x01]), [0x02]);
+        // #00ff INC2
+        assert_eq!(work(&[0xa0, 0x00, 0xff, 0x21]), [0x01, 0x00]);
+    }
+
+    #[test]
+    fn pop_byte_and_short() {
+        // #01 #02 POP
+        assert_eq!(work(&[0x80, 0x01, 0x80, 0x02, 0x02]), [0x01]);
+        // #0102 #0304 POP2
+        assert_eq!(
+            work(&[0xa0, 0x01, 0x02, 0xa0, 0x03, 0x04, 0x22]),
+            [0x01, 0x02]
+        );
+    }
+
+    #[test]
+    fn nip_byte_and_short() {
+        // #01 #02 NIP
+        assert_eq!(work(&[0x80, 0x01, 0x80, 0x02, 0x03]), [0x02]);
+        // #0102 #0304 NIP2
+        assert_eq!(
+            work(&[0xa0, 0x01, 0x02, 0xa0, 0x03, 0x04, 0x23]),
+            [0x03, 0x04]
+        );
+    }
+
+    #[test]
+    fn swp_byte_and_short() {
+        // #01 #02 SWP
+        assert_eq!(work(&[0x80, 0x01, 0x80, 0x02, 0x04]), [0x02, 0x01]);
+        // #0102 #0304 SWP2
+        assert_eq!(
+            work(&[0xa0, 0x01, 0x02, 0xa0, 0x03, 0x04, 0x24]),
+            [0x03, 0x04, 0x01, 0x02]
+        );
+    }
+
+    #[test]
+    fn rot_byte_and_short() {
+        // #01 #02 #03 ROT
+        assert_eq!(
+            work(&[0x80, 0x01, 0x80, 0x02, 0x80, 0x03, 0x05]),
+            [0x02, 0x03, 0x01]
+        );
+        // #0001 #0002 #0003 ROT2
+        assert_eq!(
+            work(&[0xa0, 0x00, 0x01, 0xa0, 0x00, 0x02, 0xa0, 0x00, 0x03, 0x25]),
+            [0x00, 0x02, 0x00, 0x03, 0x00, 0x01]
+        );
+    }
+
+    #[test]
+    fn dup_byte_and_short() {
+        // #01 DUP
+        assert_eq!(work(&[0x80, 0x01, 0x06]), [0x01, 0x01]);
+        // #0102 DUP2
+        assert_eq!(work(&[0xa0, 0x01, 0x02, 0x26]), [0x01, 0x02, 0x01, 0x02]);
+    }
+
+    #[test]
+    fn ovr_byte_and_short() {
+        // #01 #02 OVR
+        assert_eq!(work(&[0x80, 0x01, 0x80, 0x02, 0x07]), [0x01, 0x02, 0x01]);
+        // #0102 #0304 OVR2
+        assert_eq!(
+            work(&[0xa0, 0x01, 0x02, 0xa0, 0x03, 0x04, 0x27]),
+            [0x01, 0x02, 0x03, 0x04, 0x01, 0x02]
+        );
+    }
+
+    #[test]
+    fn equ_byte_and_short() {
+        // #01 #01 EQU
+        assert_eq!(work(&[0x80, 0x01, 0x80, 0x01, 0x08]), [0x01]);
+        // #0102 #0103 EQU2
+        assert_eq!(work(&[0xa0, 0x01, 0x02, 0xa0, 0x01, 0x03, 0x28]), [0x00]);
+    }
+
+    #[test]
+    fn neq_byte_and_short() {
+        // #01 #02 NEQ
+        assert_eq!(work(&[0x80, 0x01, 0x80, 0x02, 0x09]), [0x01]);
+        // #0102 #0102 NEQ2
+        assert_eq!(work(&[0xa0, 0x01, 0x02, 0xa0, 0x01, 0x02, 0x29]), [0x00]);
+    }
+
+    #[test]
+    fn gth_byte_and_short() {
+        // #02 #01 GTH
+        assert_eq!(work(&[0x80, 0x02, 0x80, 0x01, 0x0a]), [0x01]);
+        // #00ff #0100 GTH2
+        assert_eq!(work(&[0xa0, 0x00, 0xff, 0xa0, 0x01, 0x00, 0x2a]), [0x00]);
+    }
+
+    #[test]
+    fn lth_byte_and_short() {
+        // #02 #01 LTH
+        assert_eq!(work(&[0x80, 0x02, 0x80, 0x01, 0x0b]), [0x00]);
+        // #00ff #0100 LTH2
+        assert_eq!(work(&[0xa0, 0x00, 0xff, 0xa0, 0x01, 0x00, 0x2b]), [0x01]);
+    }
+
+    #[test]
+    fn jmp_byte_and_short() {
+        // #02 JMP #ff #01
+        assert_eq!(work(&[0x80, 0x02, 0x0c, 0x80, 0xff, 0x80, 0x01]), [0x01]);
+        // #0106 JMP2 #ff #01
+        assert_eq!(
+            work(&[0xa0, 0x01, 0x06, 0x2c, 0x80, 0xff, 0x80, 0x01]),
+            [0x01]
+        );
+    }
+
+    #[test]
+    fn jcn_byte_and_short() {
+        // #01 #02 JCN #ff #01
+        assert_eq!(
+            work(&[0x80, 0x01, 0x80, 0x02, 0x0d, 0x80, 0xff, 0x80, 0x01]),
+            [0x01]
+        );
+        // #00 #02 JCN #ff
+        assert_eq!(work(&[0x80, 0x00, 0x80, 0x02, 0x0d, 0x80, 0xff]), [0xff]);
+        // #01 #0108 JCN2 #ff #01
+        assert_eq!(
+            work(&[0x80, 0x01, 0xa0, 0x01, 0x08, 0x2d, 0x80, 0xff, 0x80, 0x01]),
+            [0x01]
+        );
+    }
+
+    #[test]
+    fn jsr_byte_and_short() {
+        // #02 JSR #ff #01
+        let cpu = run(
+            &mut Varvara::new(),
+            &[0x80, 0x02, 0x0e, 0x80, 0xff, 0x80, 0x01],
+        )
+        .unwrap();
+        assert_eq!(cpu.work.bytes(), [0x01]);
+        assert_eq!(cpu.ret.bytes(), [0x01, 0x03]);
+
+        // #0106 JSR2 #ff #01
+        let cpu = run(
+            &mut Varvara::new(),
+            &[0xa0, 0x01, 0x06, 0x2e, 0x80, 0xff, 0x80, 0x01],
+        )
+        .unwrap();
+        assert_eq!(cpu.work.bytes(), [0x01]);
+        assert_eq!(cpu.ret.bytes(), [0x01, 0x04]);
+    }
+
+    #[test]
+    fn sth_byte_and_short() {
+        // #05 STH
+        let cpu = run(&mut Varvara::new(), &[0x80, 0x05, 0x0f]).unwrap();
+        assert_eq!(cpu.work.bytes(), []);
+        assert_eq!(cpu.ret.bytes(), [0x05]);
+
+        // #1234 STH2
+        let cpu = run(&mut Varvara::new(), &[0xa0, 0x12, 0x34, 0x2f]).unwrap();
+        assert_eq!(cpu.ret.bytes(), [0x12, 0x34]);
+    }
+
+    #[test]
+    fn ldz_byte_and_short() {
+        let mut varvara = Varvara::new();
+        varvara.main[0x10..0x12].copy_from_slice(&[0xaa, 0xbb]);
+        // #10 LDZ #10 LDZ2
+        let cpu = run(&mut varvara, &[0x80, 0x10, 0x10, 0x80, 0x10, 0x30]).unwrap();
+        assert_eq!(cpu.work.bytes(), [0xaa, 0xaa, 0xbb]);
+    }
+
+    #[test]
+    fn stz_byte_and_short() {
+        let mut varvara = Varvara::new();
+        // #aa #10 STZ #bbcc #20 STZ2
+        run(
+            &mut varvara,
+            &[
+                0x80, 0xaa, 0x80, 0x10, 0x11, 0xa0, 0xbb, 0xcc, 0x80, 0x20, 0x31,
+            ],
+        )
+        .unwrap();
+        assert_eq!(varvara.main[0x10], 0xaa);
+        assert_eq!(varvara.main[0x20..0x22], [0xbb, 0xcc]);
+    }
+
+    #[test]
+    fn ldr_byte_and_short() {
+        // #02 LDR BRK BRK aa
+        assert_eq!(work(&[0x80, 0x02, 0x12, 0x00, 0x00, 0xaa]), [0xaa]);
+        // #02 LDR2 BRK BRK aa bb
+        assert_eq!(
+            work(&[0x80, 0x02, 0x32, 0x00, 0x00, 0xaa, 0xbb]),
+            [0xaa, 0xbb]
+        );
+    }
+
+    #[test]
+    fn str_byte_and_short() {
+        let mut varvara = Varvara::new();
+        // #aa #02 STR, landing at 0x0107
+        run(&mut varvara, &[0x80, 0xaa, 0x80, 0x02, 0x13]).unwrap();
+        assert_eq!(varvara.main[0x0107], 0xaa);
+
+        let mut varvara = Varvara::new();
+        // #aabb #02 STR2, landing at 0x0108
+        run(&mut varvara, &[0xa0, 0xaa, 0xbb, 0x80, 0x02, 0x33]).unwrap();
+        assert_eq!(varvara.main[0x0108..0x010a], [0xaa, 0xbb]);
+    }
+
+    #[test]
+    fn lda_byte_and_short() {
+        let mut varvara = Varvara::new();
+        varvara.main[0x0200..0x0202].copy_from_slice(&[0xaa, 0xbb]);
+        // #0200 LDA #0200 LDA2
+        let cpu = run(
+            &mut varvara,
+            &[0xa0, 0x02, 0x00, 0x14, 0xa0, 0x02, 0x00, 0x34],
+        )
+        .unwrap();
+        assert_eq!(cpu.work.bytes(), [0xaa, 0xaa, 0xbb]);
+    }
+
+    #[test]
+    fn load_short_wraps_past_end_of_memory() {
+        let mut varvara = Varvara::new();
+        varvara.main[0xffff] = 0x12;
+        varvara.main[0x0000] = 0x34;
+        // #ffff LDA2 BRK
+        let cpu = run(&mut varvara, &[0xa0, 0xff, 0xff, 0x34, 0x00]).unwrap();
+
+        assert_eq!(cpu.work.bytes(), [0x12, 0x34]);
+    }
+
+    #[test]
+    fn sta_byte_and_short() {
+        let mut varvara = Varvara::new();
+        // #aa #0200 STA #bbcc #0300 STA2
+        run(
+            &mut varvara,
+            &[
+                0x80, 0xaa, 0xa0, 0x02, 0x00, 0x15, 0xa0, 0xbb, 0xcc, 0xa0, 0x03, 0x00, 0x35,
+            ],
+        )
+        .unwrap();
+        assert_eq!(varvara.main[0x0200], 0xaa);
+        assert_eq!(varvara.main[0x0300..0x0302], [0xbb, 0xcc]);
+    }
+
+    #[test]
+    fn store_short_wraps_past_end_of_memory() {
+        let mut varvara = Varvara::new();
+        // #abcd #ffff STA2 BRK
+        run(
+            &mut varvara,
+            &[0xa0, 0xab, 0xcd, 0xa0, 0xff, 0xff, 0x35, 0x00],
+        )
+        .unwrap();
+
+        assert_eq!(varvara.main[0xffff], 0xab);
+        assert_eq!(varvara.main[0x0000], 0xcd);
+    }
+
+    #[test]
+    fn deo_and_dei_byte_and_short() {
+        let mut varvara = Varvara::new();
+        // #0f .System/r DEO #1234 .Screen/x DEO2 .Screen/x DEI .Screen/x DEI2
+        let cpu = run(
+            &mut varvara,
+            &[
+                0x80, 0x0f, 0x80, 0x08, 0x17, 0xa0, 0x12, 0x34, 0x80, 0x28, 0x37, 0x80, 0x28, 0x16,
+                0x80, 0x28, 0x36,
+            ],
+        )
+        .unwrap();
+        assert_eq!(varvara.io[0x08], 0x0f);
+        assert_eq!(cpu.work.bytes(), [0x12, 0x12, 0x34]);
+    }
+
+    #[test]
+    fn add_byte_and_short() {
+        // #01 #02 ADD
+        assert_eq!(work(&[0x80, 0x01, 0x80, 0x02, 0x18]), [0x03]);
+        // #00ff #0001 ADD2
+        assert_eq!(
+            work(&[0xa0, 0x00, 0xff, 0xa0, 0x00, 0x01, 0x38]),
+            [0x01, 0x00]
+        );
+    }
+
+    #[test]
+    fn sub_byte_and_short() {
+        // #03 #01 SUB
+        assert_eq!(work(&[0x80, 0x03, 0x80, 0x01, 0x19]), [0x02]);
+        // #0100 #0001 SUB2
+        assert_eq!(
+            work(&[0xa0, 0x01, 0x00, 0xa0, 0x00, 0x01, 0x39]),
+            [0x00, 0xff]
+        );
+    }
+
+    #[test]
+    fn mul_byte_and_short() {
+        // #03 #04 MUL
+        assert_eq!(work(&[0x80, 0x03, 0x80, 0x04, 0x1a]), [0x0c]);
+        // #0100 #0010 MUL2
+        assert_eq!(
+            work(&[0xa0, 0x01, 0x00, 0xa0, 0x00, 0x10, 0x3a]),
+            [0x10, 0x00]
+        );
+    }
+
+    #[test]
+    fn div_byte_and_short() {
+        // #0c #04 DIV
+        assert_eq!(work(&[0x80, 0x0c, 0x80, 0x04, 0x1b]), [0x03]);
+        // #1000 #0010 DIV2
+        assert_eq!(
+            work(&[0xa0, 0x10, 0x00, 0xa0, 0x00, 0x10, 0x3b]),
+            [0x01, 0x00]
+        );
+    }
+
+    #[test]
+    fn and_byte_and_short() {
+        // #0f #3c AND
+        assert_eq!(work(&[0x80, 0x0f, 0x80, 0x3c, 0x1c]), [0x0c]);
+        // #0f0f #00ff AND2
+        assert_eq!(
+            work(&[0xa0, 0x0f, 0x0f, 0xa0, 0x00, 0xff, 0x3c]),
+            [0x00, 0x0f]
+        );
+    }
+
+    #[test]
+    fn ora_byte_and_short() {
+        // #0f #30 ORA
+        assert_eq!(work(&[0x80, 0x0f, 0x80, 0x30, 0x1d]), [0x3f]);
+        // #0f00 #00f0 ORA2
+        assert_eq!(
+            work(&[0xa0, 0x0f, 0x00, 0xa0, 0x00, 0xf0, 0x3d]),
+            [0x0f, 0xf0]
+        );
+    }
+
+    #[test]
+    fn eor_byte_and_short() {
+        // #ff #0f EOR
+        assert_eq!(work(&[0x80, 0xff, 0x80, 0x0f, 0x1e]), [0xf0]);
+        // #ffff #0ff0 EOR2
+        assert_eq!(
+            work(&[0xa0, 0xff, 0xff, 0xa0, 0x0f, 0xf0, 0x3e]),
+            [0xf0, 0x0f]
+        );
+    }
+
+    #[test]
+    fn sft_byte_and_short() {
+        // #34 #12 SFT
+        assert_eq!(work(&[0x80, 0x34, 0x80, 0x12, 0x1f]), [0x1a]);
+        // #1234 #40 SFT2
+        assert_eq!(work(&[0xa0, 0x12, 0x34, 0x80, 0x40, 0x3f]), [0x23, 0x40]);
+        // #1234 #01 SFT2
+        assert_eq!(work(&[0xa0, 0x12, 0x34, 0x80, 0x01, 0x3f]), [0x09, 0x1a]);
+    }
+
+    #[test]
+    fn lit_byte_and_short() {
+        // #12 #3456
+        assert_eq!(work(&[0x80, 0x12, 0xa0, 0x34, 0x56]), [0x12, 0x34, 0x56]);
+    }
+
+    #[test]
+    fn jsi_calls_and_jmp2r_returns() {
+        let mut varvara = Varvara::new();
+        // routine BRK @routine #2a JMP2r
+        let cpu = run(&mut varvara, &[0x60, 0x00, 0x01, 0x00, 0x80, 0x2a, 0x6c]).unwrap();
+
+        assert_eq!(cpu.work.bytes(), [0x2a]);
+        assert_eq!(cpu.ret.bytes(), []);
+    }
+
+    #[test]
+    fn jci_jumps_on_nonzero() {
+        let mut varvara = Varvara::new();
+        // #01 ?skip #ff @skip BRK
+        let cpu = run(
+            &mut varvara,
+            &[0x80, 0x01, 0x20, 0x00, 0x02, 0x80, 0xff, 0x00],
+        )
+        .unwrap();
+
+        assert_eq!(cpu.work.bytes(), []);
+    }
+
+    #[test]
+    fn jci_falls_through_on_zero() {
+        let mut varvara = Varvara::new();
+        // #00 ?skip #ff @skip BRK
+        let cpu = run(
+            &mut varvara,
+            &[0x80, 0x00, 0x20, 0x00, 0x02, 0x80, 0xff, 0x00],
+        )
+        .unwrap();
+
+        assert_eq!(cpu.work.bytes(), [0xff]);
+    }
+
+    #[test]
+    fn jmi_always_jumps() {
+        let mut varvara = Varvara::new();
+        // !skip #ff @skip BRK
+        let cpu = run(&mut varvara, &[0x40, 0x00, 0x02, 0x80, 0xff, 0x00]).unwrap();
+
+        assert_eq!(cpu.work.bytes(), []);
+    }
+
+    #[test]
+    fn lit_and_arithmetic_in_return_mode() {
+        // LITr 01 LITr 02 ADDr
+        let cpu = run(&mut Varvara::new(), &[0xc0, 0x01, 0xc0, 0x02, 0x58]).unwrap();
+        assert_eq!(cpu.work.bytes(), []);
+        assert_eq!(cpu.ret.bytes(), [0x03]);
+
+        // LIT2r 00ff LIT2r 0001 ADD2r
+        let cpu = run(
+            &mut Varvara::new(),
+            &[0xe0, 0x00, 0xff, 0xe0, 0x00, 0x01, 0x78],
+        )
+        .unwrap();
+        assert_eq!(cpu.work.bytes(), []);
+        assert_eq!(cpu.ret.bytes(), [0x01, 0x00]);
+    }
+
+    #[test]
+    fn jsr_return_mode_pushes_to_working_stack() {
+        // LITr 02 JSRr #ff #01
+        let cpu = run(
+            &mut Varvara::new(),
+            &[0xc0, 0x02, 0x4e, 0x80, 0xff, 0x80, 0x01],
+        )
+        .unwrap();
+        assert_eq!(cpu.work.bytes(), [0x01, 0x03, 0x01]);
+        assert_eq!(cpu.ret.bytes(), []);
+
+        // LIT2r 0106 JSR2r #ff #01
+        let cpu = run(
+            &mut Varvara::new(),
+            &[0xe0, 0x01, 0x06, 0x6e, 0x80, 0xff, 0x80, 0x01],
+        )
+        .unwrap();
+        assert_eq!(cpu.work.bytes(), [0x01, 0x04, 0x01]);
+        assert_eq!(cpu.ret.bytes(), []);
+    }
+
+    #[test]
+    fn sth_return_mode_moves_to_working_stack() {
+        // LITr 05 STHr LITr 06
+        let cpu = run(&mut Varvara::new(), &[0xc0, 0x05, 0x4f, 0xc0, 0x06]).unwrap();
+        assert_eq!(cpu.work.bytes(), [0x05]);
+        assert_eq!(cpu.ret.bytes(), [0x06]);
+    }
+
+    #[test]
+    fn memory_in_return_mode() {
+        let mut varvara = Varvara::new();
+        varvara.main[0x10] = 0xaa;
+        // LITr 10 LDZr LITr bb LIT2r 0200 STAr
+        let cpu = run(
+            &mut varvara,
+            &[0xc0, 0x10, 0x50, 0xc0, 0xbb, 0xe0, 0x02, 0x00, 0x55],
+        )
+        .unwrap();
+        assert_eq!(cpu.ret.bytes(), [0xaa]);
+        assert_eq!(varvara.main[0x0200], 0xbb);
+    }
+
+    #[test]
+    fn deo_return_mode() {
+        let mut varvara = Varvara::new();
+        // LITr 0f LITr 08 DEOr
+        let cpu = run(&mut varvara, &[0xc0, 0x0f, 0xc0, 0x08, 0x57]).unwrap();
+        assert_eq!(varvara.io[0x08], 0x0f);
+        assert_eq!(cpu.ret.bytes(), []);
+    }
+
+    #[test]
+    fn system_vector_catches_faults() {
+        let mut varvara = Varvara::new();
+        // ;on-error .System/vector DEO2 #01 #00 DIV BRK
+        // @on-error #03 STZ #02 STZ #00 STZ2 BRK
+        let program = [
+            0xa0, 0x01, 0x0c, 0x80, 0x00, 0x37, 0x80, 0x01, 0x80, 0x00, 0x1b, 0x00, 0x80, 0x03,
+            0x11, 0x80, 0x02, 0x11, 0x80, 0x00, 0x31, 0x00,
+        ];
+        let cpu = run(&mut varvara, &program).unwrap();
+
+        assert_eq!(varvara.main[0x00..0x04], [0x01, 0x0a, 0x1b, 0x03]);
+        assert_eq!(cpu.work.bytes(), []);
+    }
+
+    #[test]
+    fn keep_reads_without_consuming() {
+        let mut stack = Stack::new();
+        stack.push(0x02).unwrap();
+        stack.push(0x03).unwrap();
+
+        stack.keep();
+        let b = stack.pop().unwrap();
+        let a = stack.pop().unwrap();
+        stack.push(a + b).unwrap();
+        stack.release();
+
+        assert_eq!(stack.pop(), Ok(0x05));
+        assert_eq!(stack.pop(), Ok(0x03));
+        assert_eq!(stack.pop(), Ok(0x02));
+    }
+
+    #[test]
+    fn keep_short() {
+        let mut stack = Stack::new();
+        stack.push2(0x1234).unwrap();
+
+        stack.keep();
+        let a = stack.pop2().unwrap();
+        stack.push2(a).unwrap();
+        stack.release();
+
+        assert_eq!(stack.pop2(), Ok(0x1234));
+        assert_eq!(stack.pop2(), Ok(0x1234));
+    }
+
+    #[test]
+    fn add_short_keep() {
+        let mut varvara = Varvara::new();
+        // #0001 #0002 ADD2k BRK
+        let cpu = run(
+            &mut varvara,
+            &[0xa0, 0x00, 0x01, 0xa0, 0x00, 0x02, 0xb8, 0x00],
+        )
+        .unwrap();
+
+        assert_eq!(cpu.work.bytes(), [0x00, 0x01, 0x00, 0x02, 0x00, 0x03]);
+    }
+
+    #[test]
+    fn underflow() {
+        let mut stack = Stack::new();
+        stack.push(0x01).unwrap();
+
+        assert_eq!(stack.pop2(), Err(Fault::Underflow));
+    }
+
+    #[test]
+    fn keep_underflow() {
+        let mut stack = Stack::new();
+        stack.push(0x01).unwrap();
+
+        stack.keep();
+        stack.pop().unwrap();
+        assert_eq!(stack.pop(), Err(Fault::Underflow));
+    }
+
+    #[test]
+    fn overflow() {
+        let mut stack = Stack::new();
+        for _ in 0..0xff {
+            stack.push(0x01).unwrap();
+        }
+
+        assert_eq!(stack.push(0x01), Err(Fault::Overflow));
+    }
+
+    #[test]
+    fn underflow_halts() {
+        let mut varvara = Varvara::new();
+        // POP BRK
+        let result = run(&mut varvara, &[0x02, 0x00]);
+
+        let halt = Halt {
+            fault: Fault::Underflow,
+            instr: 0x02,
+            addr: 0x0100,
+        };
+        assert_eq!(result.err(), Some(EmuError::StackFault(halt)));
+    }
+
+    #[test]
+    fn division_by_zero_halts() {
+        let mut varvara = Varvara::new();
+        // #01 #00 DIV BRK
+        let result = run(&mut varvara, &[0x80, 0x01, 0x80, 0x00, 0x1b, 0x00]);
+
+        let halt = Halt {
+            fault: Fault::DivisionByZero,
+            instr: 0x1b,
+            addr: 0x0104,
+        };
+        assert_eq!(result.err(), Some(EmuError::StackFault(halt)));
+    }
+
+    #[test]
+    fn short_division_by_zero_halts() {
+        // #0001 #0000 DIV2
+        let result = run(
+            &mut Varvara::new(),
+            &[0xa0, 0x00, 0x01, 0xa0, 0x00, 0x00, 0x3b],
+        );
+
+        let halt = Halt {
+            fault: Fault::DivisionByZero,
+            instr: 0x3b,
+            addr: 0x0106,
+        };
+        assert_eq!(result.err(), Some(EmuError::StackFault(halt)));
+    }
+
+    #[test]
+    fn halt_message() {
+        let halt = Halt {
+            fault: Fault::Underflow,
+            instr: 0x38,
+            addr: 0x0104,
+        };
+
+        assert_eq!(
+            halt.to_string(),
+            "Working-stack underflow, by ADD2 at 0x0104."
+        );
+    }
+}
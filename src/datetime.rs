@@ -0,0 +1,205 @@
+use chrono::{Datelike, Duration, Local, NaiveDate, NaiveDateTime, Offset, TimeZone, Timelike};
+
+use super::error::EmuError;
+use super::varvara::Device;
+
+/// A moment as the Datetime device reports it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LocalTime {
+    pub time: NaiveDateTime,
+    pub isdst: bool,
+}
+
+/// Where the Datetime device gets the time from
+pub trait Clock {
+    fn now(&mut self) -> LocalTime;
+}
+
+/// The host's local time
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&mut self) -> LocalTime {
+        let now = Local::now();
+        // chrono doesn't know about daylight saving, so guess it from the
+        // offset being ahead of the smaller of January's and July's
+        let offset_in = |month| {
+            Local
+                .with_ymd_and_hms(now.year(), month, 1, 0, 0, 0)
+                .earliest()
+                .map(|t| t.offset().fix().local_minus_utc())
+        };
+        let standard = match (offset_in(1), offset_in(7)) {
+            (Some(jan), Some(jul)) => jan.min(jul),
+            _ => now.offset().fix().local_minus_utc(),
+        };
+        LocalTime {
+            time: now.naive_local(),
+            isdst: now.offset().fix().local_minus_utc() > standard,
+        }
+    }
+}
+
+/// A clock for reproducible runs. It starts at a fixed time and moves
+/// forward by `step` every time it is read, so a zero step keeps it still.
+pub struct FakeClock {
+    time: NaiveDateTime,
+    step: Duration,
+}
+
+impl FakeClock {
+    pub fn new(time: NaiveDateTime, step: Duration) -> Self {
+        Self { time, step }
+    }
+
+    /// A clock stopped at `time`
+    pub fn fixed(time: NaiveDateTime) -> Self {
+        Self::new(time, Duration::zero())
+    }
+}
+
+impl Default for FakeClock {
+    /// Stopped at midnight on 2000-01-01
+    fn default() -> Self {
+        let time = NaiveDate::from_ymd_opt(2000, 1, 1)
+            .and_then(|date| date.and_hms_opt(0, 0, 0))
+            .expect("valid date");
+        Self::fixed(time)
+    }
+}
+
+impl Clock for FakeClock {
+    fn now(&mut self) -> LocalTime {
+        let time = self.time;
+        self.time += self.step;
+        LocalTime { time, isdst: false }
+    }
+}
+
+/// The Datetime device. Every read asks its clock for the time.
+pub struct Datetime {
+    clock: Box<dyn Clock>,
+}
+
+impl Datetime {
+    pub fn new(clock: impl Clock + 'static) -> Self {
+        Self {
+            clock: Box::new(clock),
+        }
+    }
+
+    /// The device's ports filled in with the current time
+    fn ports(&mut self) -> [u8; 0x10] {
+        let LocalTime { time, isdst } = self.clock.now();
+        let [year_high, year_low] = (time.year() as u16).to_be_bytes();
+        let [doty_high, doty_low] = (time.ordinal0() as u16).to_be_bytes();
+        let mut ports = [0; 0x10];
+        ports[..0xb].copy_from_slice(&[
+            year_high,
+            year_low,
+            time.month0() as u8,
+            time.day() as u8,
+            time.hour() as u8,
+            time.minute() as u8,
+            time.second() as u8,
+            time.weekday().num_days_from_sunday() as u8,
+            doty_high,
+            doty_low,
+            isdst as u8,
+        ]);
+        ports
+    }
+}
+
+impl Default for Datetime {
+    fn default() -> Self {
+        Self::new(SystemClock)
+    }
+}
+
+impl Device for Datetime {
+    fn dei(&mut self, _io: &[u8], addr: u8) -> Result<u8, EmuError> {
+        let port = addr & 0x0F;
+        Ok(self.ports()[port as usize])
+    }
+
+    fn dei2(&mut self, _io: &[u8], addr: u8) -> Result<u16, EmuError> {
+        let port = (addr & 0x0F) as usize;
+        let ports = self.ports();
+        Ok(u16::from_be_bytes([ports[port], ports[(port + 1) & 0x0F]]))
+    }
+
+    fn deo(
+        &mut self,
+        _io: &mut [u8],
+        _main: &mut [u8],
+        _addr: u8,
+        _byte: u8,
+    ) -> Result<(), EmuError> {
+        // the clock can't be set, so writes are ignored
+        Ok(())
+    }
+
+    fn deo2(
+        &mut self,
+        _io: &mut [u8],
+        _main: &mut [u8],
+        _addr: u8,
+        _short: u16,
+    ) -> Result<(), EmuError> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::varvara::Varvara;
+
+    fn time(y: i32, mo: u32, d: u32, h: u32, mi: u32, s: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(y, mo, d)
+            .and_then(|date| date.and_hms_opt(h, mi, s))
+            .unwrap()
+    }
+
+    #[test]
+    fn fixed_clock_fills_every_port() {
+        let mut varvara = Varvara::new();
+        let clock = FakeClock::fixed(time(2024, 3, 5, 13, 7, 42));
+        varvara.register(0xc, Box::new(Datetime::new(clock)));
+
+        assert_eq!(varvara.dei2(0xc0), Ok(2024));
+        assert_eq!(varvara.dei(0xc2), Ok(2));
+        assert_eq!(varvara.dei(0xc3), Ok(5));
+        assert_eq!(varvara.dei(0xc4), Ok(13));
+        assert_eq!(varvara.dei(0xc5), Ok(7));
+        assert_eq!(varvara.dei(0xc6), Ok(42));
+        // a Tuesday
+        assert_eq!(varvara.dei(0xc7), Ok(2));
+        assert_eq!(varvara.dei2(0xc8), Ok(31 + 29 + 4));
+        assert_eq!(varvara.dei(0xca), Ok(0));
+    }
+
+    #[test]
+    fn stepping_clock_moves_on_each_read() {
+        let mut varvara = Varvara::new();
+        let clock = FakeClock::new(time(1999, 12, 31, 23, 59, 59), Duration::seconds(1));
+        varvara.register(0xc, Box::new(Datetime::new(clock)));
+
+        assert_eq!(varvara.dei(0xc6), Ok(59));
+        assert_eq!(varvara.dei2(0xc0), Ok(2000));
+        assert_eq!(varvara.dei(0xc6), Ok(1));
+    }
+
+    #[test]
+    fn writes_are_ignored() {
+        let mut varvara = Varvara::new();
+        let clock = FakeClock::fixed(time(2024, 3, 5, 13, 7, 42));
+        varvara.register(0xc, Box::new(Datetime::new(clock)));
+
+        assert_eq!(varvara.deo(0xc6, 0x00), Ok(()));
+        assert_eq!(varvara.deo2(0xc0, 0x0000), Ok(()));
+        assert_eq!(varvara.dei(0xc6), Ok(42));
+        assert_eq!(varvara.dei2(0xc0), Ok(2024));
+    }
+}
@@ -0,0 +1,35 @@
+use std::fmt;
+
+use super::cpu::Halt;
+
+/// Everything that can stop the emulator short of a clean BRK
+#[derive(Debug, Clone, PartialEq)]
+pub enum EmuError {
+    /// A device port with nothing behind it, by address in the device page
+    UnknownPort(u8),
+    /// An opcode byte the CPU can't execute
+    UnimplementedOpcode(u8),
+    /// Stack underflow, stack overflow or division by zero
+    StackFault(Halt),
+    /// A memory access past the end of the memory it targets
+    OutOfRange(usize),
+}
+
+impl fmt::Display for EmuError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EmuError::UnknownPort(port) => write!(f, "Unknown port 0x{port:02x}."),
+            EmuError::UnimplementedOpcode(byte) => write!(f, "Unimplemented opcode 0x{byte:02x}."),
+            EmuError::StackFault(halt) => write!(f, "{halt}"),
+            EmuError::OutOfRange(addr) => write!(f, "Memory access out of range at 0x{addr:04x}."),
+        }
+    }
+}
+
+impl std::error::Error for EmuError {}
+
+impl From<Halt> for EmuError {
+    fn from(halt: Halt) -> Self {
+        EmuError::StackFault(halt)
+    }
+}
@@ -0,0 +1,391 @@
+use std::collections::VecDeque;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Component, Path, PathBuf};
+
+use super::error::EmuError;
+use super::varvara::{read_short, write_short, Device};
+
+/// What the device is in the middle of. Reads and writes carry on from
+/// where the last one stopped until a new name is set.
+enum FileState {
+    Idle,
+    Reading(fs::File),
+    /// Reading a directory, whose listing is made when it is opened. Each
+    /// read hands out as many whole entries as fit, or a piece of an entry
+    /// too long for the read.
+    Listing(VecDeque<Vec<u8>>),
+    Writing(fs::File),
+}
+
+/// A File device. ROMs can only reach paths inside its root directory.
+pub struct File {
+    root: PathBuf,
+    state: FileState,
+}
+
+impl File {
+    /// A File device sandboxed to `root`
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            state: FileState::Idle,
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// The host path named by the name port, if it stays inside the root
+    fn path(&self, io: &[u8], main: &[u8], base: u8) -> Option<PathBuf> {
+        let addr = read_short(io, base | 0x8) as usize;
+        let name = &main[addr..];
+        let end = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+        sandboxed(&self.root, &String::from_utf8_lossy(&name[..end]))
+    }
+
+    fn read(&mut self, path: Option<PathBuf>, dest: &mut [u8]) -> u16 {
+        if !matches!(self.state, FileState::Reading(_) | FileState::Listing(_)) {
+            self.state = match path {
+                Some(path) if path.is_dir() => match listing(&path) {
+                    Ok(listing) => {
+                        FileState::Listing(listing.into_iter().map(String::into_bytes).collect())
+                    }
+                    Err(_) => FileState::Idle,
+                },
+                Some(path) => match fs::File::open(path) {
+                    Ok(file) => FileState::Reading(file),
+                    Err(_) => FileState::Idle,
+                },
+                None => FileState::Idle,
+            };
+        }
+        let file = match &mut self.state {
+            FileState::Reading(file) => file,
+            FileState::Listing(listing) => return read_entries(listing, dest),
+            _ => return 0,
+        };
+        let mut filled = 0;
+        while filled < dest.len() {
+            match file.read(&mut dest[filled..]) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => filled += n,
+            }
+        }
+        filled as u16
+    }
+
+    fn write(&mut self, path: Option<PathBuf>, append: bool, src: &[u8]) -> u16 {
+        if !matches!(self.state, FileState::Writing(_)) {
+            let file = path.map(|path| {
+                fs::OpenOptions::new()
+                    .write(true)
+                    .create(true)
+                    .append(append)
+                    .truncate(!append)
+                    .open(path)
+            });
+            self.state = match file {
+                Some(Ok(file)) => FileState::Writing(file),
+                _ => FileState::Idle,
+            };
+        }
+        let FileState::Writing(file) = &mut self.state else {
+            return 0;
+        };
+        match file.write_all(src) {
+            Ok(()) => src.len() as u16,
+            Err(_) => 0,
+        }
+    }
+}
+
+impl Device for File {
+    fn deo(&mut self, io: &mut [u8], main: &mut [u8], addr: u8, _byte: u8) -> Result<(), EmuError> {
+        let base = addr & 0xF0;
+        let port = addr & 0x0F;
+        if port == 0x6 {
+            self.state = FileState::Idle;
+            let deleted = match self.path(io, main, base) {
+                Some(path) => fs::remove_file(path).is_ok(),
+                None => false,
+            };
+            write_short(io, base | 0x2, deleted as u16);
+        }
+        Ok(())
+    }
+
+    fn deo2(
+        &mut self,
+        io: &mut [u8],
+        main: &mut [u8],
+        addr: u8,
+        short: u16,
+    ) -> Result<(), EmuError> {
+        let base = addr & 0xF0;
+        let port = addr & 0x0F;
+        // the region of main memory a stat, read or write works on, cut off
+        // at the end of memory
+        let start = short as usize;
+        let end = (start + read_short(io, base | 0xa) as usize).min(main.len());
+        let success = match port {
+            0x4 => {
+                let path = self.path(io, main, base);
+                stat(path, &mut main[start..end])
+            }
+            0x8 => {
+                self.state = FileState::Idle;
+                return Ok(());
+            }
+            0xc => {
+                let path = self.path(io, main, base);
+                self.read(path, &mut main[start..end])
+            }
+            0xe => {
+                let path = self.path(io, main, base);
+                let append = io[(base | 0x7) as usize] != 0;
+                self.write(path, append, &main[start..end])
+            }
+            0x0 | 0x2 | 0xa => return Ok(()),
+            _ => return Err(EmuError::UnknownPort(addr)),
+        };
+        write_short(io, base | 0x2, success);
+        Ok(())
+    }
+}
+
+/// `name` joined onto `root`, unless it is absolute, climbs out with `..`
+/// or leads out through a symlink
+fn sandboxed(root: &Path, name: &str) -> Option<PathBuf> {
+    let name = Path::new(name);
+    let inside = name
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+    if !inside {
+        return None;
+    }
+    let path = root.join(name);
+    let root = root.canonicalize().ok()?;
+    // a file that doesn't exist yet is checked by its directory, but a
+    // dangling symlink would still be followed when it's created
+    let real = match path.canonicalize() {
+        Ok(real) => real,
+        Err(_) if path.symlink_metadata().is_ok() => return None,
+        Err(_) => path.parent()?.canonicalize().ok()?.join(path.file_name()?),
+    };
+    real.starts_with(&root).then_some(real)
+}
+
+/// Copy whole entries off the front of a listing into `dest` until the next
+/// one doesn't fit, leaving it for the next read. An entry longer than all of
+/// `dest` is split, so the listing never stalls.
+fn read_entries(listing: &mut VecDeque<Vec<u8>>, dest: &mut [u8]) -> u16 {
+    let mut filled = 0;
+    while let Some(entry) = listing.front_mut() {
+        let end = filled + entry.len();
+        if end > dest.len() {
+            if filled == 0 {
+                dest.copy_from_slice(&entry[..dest.len()]);
+                entry.drain(..dest.len());
+                filled = dest.len();
+            }
+            break;
+        }
+        dest[filled..end].copy_from_slice(entry);
+        filled = end;
+        listing.pop_front();
+    }
+    filled as u16
+}
+
+/// A directory's entries, sorted, one `XXXX name` line each. `XXXX` is
+/// the size in hex, `----` for subdirectories and `????` for files over
+/// 0xFFFF bytes. Subdirectory names end with `/`.
+fn listing(dir: &Path) -> std::io::Result<Vec<String>> {
+    let mut entries = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            match entry.metadata() {
+                Ok(meta) if meta.is_dir() => format!("---- {name}/\n"),
+                Ok(meta) if meta.len() <= 0xFFFF => format!("{:04x} {name}\n", meta.len()),
+                _ => format!("???? {name}\n"),
+            }
+        })
+        .collect::<Vec<_>>();
+    // sorting by name, after the size column
+    entries.sort_by(|a, b| a[5..].cmp(&b[5..]));
+    Ok(entries)
+}
+
+/// Fill `dest` with the size of the file as hex digits. Directories fill it
+/// with `-`, missing files with `!` and sizes that don't fit with `?`.
+fn stat(path: Option<PathBuf>, dest: &mut [u8]) -> u16 {
+    let fill = match path.map(fs::metadata) {
+        Some(Ok(meta)) if meta.is_dir() => Some(b'-'),
+        Some(Ok(meta)) => {
+            let digits = format!("{:0width$x}", meta.len(), width = dest.len());
+            if digits.len() > dest.len() {
+                Some(b'?')
+            } else {
+                dest.copy_from_slice(digits.as_bytes());
+                None
+            }
+        }
+        _ => Some(b'!'),
+    };
+    if let Some(fill) = fill {
+        dest.fill(fill);
+    }
+    dest.len() as u16
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::varvara::Varvara;
+
+    /// A Varvara whose first File device is rooted in a fresh temp directory
+    fn sandbox(name: &str) -> (Varvara, PathBuf) {
+        let root = std::env::temp_dir().join(format!("uxn-file-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        let mut varvara = Varvara::new();
+        varvara.register(0xa, Box::new(File::new(&root)));
+        (varvara, root)
+    }
+
+    fn set_name(varvara: &mut Varvara, name: &str) {
+        varvara.main[0x1000..0x1000 + name.len()].copy_from_slice(name.as_bytes());
+        varvara.main[0x1000 + name.len()] = 0;
+        varvara.deo2(0xa8, 0x1000).unwrap();
+    }
+
+    #[test]
+    fn write_then_read_back() {
+        let (mut varvara, root) = sandbox("write-read");
+        varvara.main[0x2000..0x2005].copy_from_slice(b"hello");
+        set_name(&mut varvara, "note.txt");
+        varvara.deo2(0xaa, 5).unwrap();
+        varvara.deo2(0xae, 0x2000).unwrap();
+        assert_eq!(varvara.dei2(0xa2), Ok(5));
+
+        set_name(&mut varvara, "note.txt");
+        varvara.deo2(0xaa, 0x10).unwrap();
+        varvara.deo2(0xac, 0x3000).unwrap();
+        assert_eq!(varvara.dei2(0xa2), Ok(5));
+        assert_eq!(&varvara.main[0x3000..0x3005], b"hello");
+        assert_eq!(fs::read(root.join("note.txt")).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn append_keeps_contents() {
+        let (mut varvara, root) = sandbox("append");
+        fs::write(root.join("log"), "ab").unwrap();
+        varvara.main[0x2000] = b'c';
+        set_name(&mut varvara, "log");
+        varvara.deo(0xa7, 0x01).unwrap();
+        varvara.deo2(0xaa, 1).unwrap();
+        varvara.deo2(0xae, 0x2000).unwrap();
+        assert_eq!(fs::read(root.join("log")).unwrap(), b"abc");
+    }
+
+    #[test]
+    fn stat_and_delete() {
+        let (mut varvara, root) = sandbox("stat-delete");
+        fs::write(root.join("data"), [0; 0x12]).unwrap();
+        set_name(&mut varvara, "data");
+        varvara.deo2(0xaa, 4).unwrap();
+        varvara.deo2(0xa4, 0x2000).unwrap();
+        assert_eq!(&varvara.main[0x2000..0x2004], b"0012");
+
+        varvara.deo(0xa6, 0x01).unwrap();
+        assert_eq!(varvara.dei2(0xa2), Ok(1));
+        varvara.deo2(0xa4, 0x2000).unwrap();
+        assert_eq!(&varvara.main[0x2000..0x2004], b"!!!!");
+        assert!(!root.join("data").exists());
+    }
+
+    #[test]
+    fn directory_listing_streams_across_reads() {
+        let (mut varvara, root) = sandbox("listing");
+        fs::create_dir(root.join("sub")).unwrap();
+        fs::write(root.join("a.txt"), [0; 0x1f]).unwrap();
+        fs::write(root.join("big"), vec![0; 0x10000]).unwrap();
+        set_name(&mut varvara, "");
+
+        // only the first entry fits, so the read stops there
+        varvara.deo2(0xaa, 0x10).unwrap();
+        varvara.deo2(0xac, 0x2000).unwrap();
+        assert_eq!(varvara.dei2(0xa2), Ok(0x0b));
+        assert_eq!(&varvara.main[0x2000..0x2010], b"001f a.txt\n\0\0\0\0\0");
+
+        varvara.deo2(0xaa, 0x100).unwrap();
+        varvara.deo2(0xac, 0x2000).unwrap();
+        assert_eq!(varvara.dei2(0xa2), Ok(0x13));
+        assert_eq!(&varvara.main[0x2000..0x2013], b"???? big\n---- sub/\n");
+
+        varvara.deo2(0xac, 0x2000).unwrap();
+        assert_eq!(varvara.dei2(0xa2), Ok(0));
+    }
+
+    #[test]
+    fn long_entries_are_split_across_reads() {
+        let (mut varvara, root) = sandbox("short-reads");
+        fs::write(root.join("a.txt"), [0; 0x1f]).unwrap();
+        set_name(&mut varvara, "");
+        varvara.deo2(0xaa, 4).unwrap();
+
+        let mut read = Vec::new();
+        for expected in [4, 4, 3, 0] {
+            varvara.deo2(0xac, 0x2000).unwrap();
+            assert_eq!(varvara.dei2(0xa2), Ok(expected));
+            read.extend_from_slice(&varvara.main[0x2000..0x2000 + expected as usize]);
+        }
+        assert_eq!(read, b"001f a.txt\n");
+    }
+
+    #[test]
+    fn escapes_are_rejected() {
+        let (mut varvara, root) = sandbox("escape");
+        let outside = root.with_extension("outside");
+        fs::write(&outside, "secret").unwrap();
+        varvara.deo2(0xaa, 6).unwrap();
+
+        let relative = format!("../{}", outside.file_name().unwrap().to_str().unwrap());
+        for name in [&relative, outside.to_str().unwrap()] {
+            set_name(&mut varvara, name);
+            varvara.deo2(0xac, 0x2000).unwrap();
+            assert_eq!(varvara.dei2(0xa2), Ok(0));
+            varvara.deo2(0xae, 0x2000).unwrap();
+            assert_eq!(varvara.dei2(0xa2), Ok(0));
+        }
+        assert_eq!(fs::read(&outside).unwrap(), b"secret");
+        fs::remove_file(outside).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_out_of_the_root_are_rejected() {
+        let (mut varvara, root) = sandbox("symlink");
+        let outside = root.with_extension("outside");
+        fs::write(&outside, "secret").unwrap();
+        std::os::unix::fs::symlink(&outside, root.join("link")).unwrap();
+        std::os::unix::fs::symlink(root.with_extension("gone"), root.join("dangling")).unwrap();
+        std::os::unix::fs::symlink(std::env::temp_dir(), root.join("tmp")).unwrap();
+        varvara.main[0x2000..0x2006].copy_from_slice(b"hacked");
+        varvara.deo2(0xaa, 6).unwrap();
+
+        for name in ["link", "dangling", "tmp", "tmp/new"] {
+            set_name(&mut varvara, name);
+            varvara.deo2(0xac, 0x3000).unwrap();
+            assert_eq!(varvara.dei2(0xa2), Ok(0));
+            varvara.deo2(0xae, 0x2000).unwrap();
+            assert_eq!(varvara.dei2(0xa2), Ok(0));
+        }
+        assert_eq!(fs::read(&outside).unwrap(), b"secret");
+        assert!(!root.with_extension("gone").exists());
+        fs::remove_file(outside).unwrap();
+    }
+}
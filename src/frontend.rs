@@ -0,0 +1,67 @@
+use std::collections::VecDeque;
+
+use super::console::InputType;
+use super::controller::Button;
+use super::mouse::MouseButton;
+use super::varvara::Varvara;
+
+/// Something that happened outside the machine, which runs a device vector
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Event {
+    /// Time for a new frame, which runs the Screen vector
+    Frame,
+    /// A controller button went down
+    Press(Button),
+    /// A controller button came back up
+    Release(Button),
+    /// A character was typed
+    Key(u8),
+    /// The mouse moved to a spot on the screen
+    MouseMove { x: u16, y: u16 },
+    /// A mouse button went down
+    MousePress(MouseButton),
+    /// A mouse button came back up
+    MouseRelease(MouseButton),
+    /// The scroll wheel moved
+    MouseScroll { x: i16, y: i16 },
+    /// A byte of console input came in
+    Console(u8, InputType),
+}
+
+/// Something that presents a running Varvara machine, like a window
+pub trait Frontend {
+    /// Whether the frontend wants the machine to keep running
+    fn is_open(&self) -> bool;
+
+    /// Events that came in since the last poll
+    fn poll(&mut self) -> Vec<Event>;
+
+    /// Show the current state of the machine
+    fn update(&mut self, varvara: &Varvara);
+}
+
+/// A headless frontend that plays back scripted input, one batch of events
+/// per poll, and closes once it runs out
+pub struct Script {
+    batches: VecDeque<Vec<Event>>,
+}
+
+impl Script {
+    pub fn new(batches: Vec<Vec<Event>>) -> Self {
+        Self {
+            batches: batches.into(),
+        }
+    }
+}
+
+impl Frontend for Script {
+    fn is_open(&self) -> bool {
+        !self.batches.is_empty()
+    }
+
+    fn poll(&mut self) -> Vec<Event> {
+        self.batches.pop_front().unwrap_or_default()
+    }
+
+    fn update(&mut self, _varvara: &Varvara) {}
+}
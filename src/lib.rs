@@ -1,8 +1,18 @@
+pub mod audio;
 pub mod console;
+pub mod controller;
 pub mod cpu;
+pub mod datetime;
+pub mod error;
+pub mod file;
+pub mod frontend;
+pub mod metadata;
+pub mod mouse;
 pub mod opcode;
 pub mod parse;
 pub mod screen;
 pub mod system;
 pub mod tal;
 pub mod varvara;
+#[cfg(feature = "window")]
+pub mod window;
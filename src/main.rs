@@ -1,46 +1,75 @@
-use std::fs::File;
+use std::env;
+use std::fs;
 use std::io;
-use std::io::prelude::*;
-
-use minifb::Key;
+use std::process::ExitCode;
 
+use uxn::console::Console;
 use uxn::cpu::Cpu;
+use uxn::file::File;
 use uxn::tal;
 use uxn::varvara::Varvara;
+#[cfg(feature = "window")]
+use uxn::window::Window;
+
+/// Shown when no ROM is given
+const DEMO_TAL: &str = "roms/test/hello_2bpp_sprites_sq.tal";
+const DEMO_ROM: &str = "roms/test/hello_2bpp_sprites_sq.rom";
+
+/// Usage: `uxn [--root dir] [rom [args...]]`. The args go to the ROM through
+/// the Console, and the File devices can only reach paths inside `dir`, which
+/// is the working directory by default.
+/// Exits with the code the ROM halts with through System/state, if it does,
+/// or with 1 if the window can't open or draw, or the emulator stops on an
+/// error.
+fn main() -> io::Result<ExitCode> {
+    let mut args = env::args().skip(1).peekable();
+    let root = args.next_if_eq("--root").and_then(|_| args.next());
+    let rom_path = match args.next() {
+        Some(path) => path,
+        None => {
+            tal::assemble(DEMO_TAL, DEMO_ROM).expect("failed to assemble");
+            DEMO_ROM.to_string()
+        }
+    };
 
-fn main() -> io::Result<()> {
     let mut varvara = Varvara::new();
+    if let Some(root) = root {
+        varvara.register(0xa, Box::new(File::new(&root)));
+        varvara.register(0xb, Box::new(File::new(&root)));
+    }
     let mut uxn = Cpu::new();
 
-    tal::assemble(
-        "roms/test/hello_2bpp_sprites_sq.tal",
-        "roms/test/hello_2bpp_sprites_sq.rom",
-    )
-    .expect("failed to assemble");
-
+    let rom = fs::read(&rom_path)?;
     let rom_load_area = &mut varvara.main[0x0100..];
-    let mut file =
-        File::open("roms/test/hello_2bpp_sprites_sq.rom").expect("failed to open rom file");
-    let _n = file.read(rom_load_area).expect("failed to read rom file");
-
-    for i in varvara.screen.buffer.iter_mut() {
-        *i = 0x00;
+    let len = rom.len().min(rom_load_area.len());
+    rom_load_area[..len].copy_from_slice(&rom[..len]);
+    if let Some(console) = varvara.device_mut::<Console>() {
+        console.queue_args(args);
     }
 
-    loop {
-        let terminate = uxn.step(&mut varvara);
-        if terminate {
-            break;
+    #[cfg(feature = "window")]
+    let result = match Window::new() {
+        Ok(mut window) => {
+            let result = varvara.run(&mut uxn, &mut window);
+            if window.failed() {
+                return Ok(ExitCode::FAILURE);
+            }
+            result
         }
-    }
+        Err(error) => {
+            eprintln!("{error}");
+            return Ok(ExitCode::FAILURE);
+        }
+    };
+    #[cfg(not(feature = "window"))]
+    let result = varvara
+        .reset(&mut uxn)
+        .and_then(|()| varvara.stream_console(&mut uxn, io::stdin()));
 
-    while varvara.window.is_open() && !varvara.window.is_key_down(Key::Escape) {
-        varvara.update_window();
+    if let Err(error) = result {
+        eprintln!("{error}");
+        return Ok(ExitCode::FAILURE);
     }
 
-    Ok(())
-}
-
-fn _print_bytes(data: &[u8]) {
-    println!("{:0>2x?}", &data);
+    Ok(ExitCode::from(varvara.exit_code().unwrap_or(0)))
 }
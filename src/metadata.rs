@@ -0,0 +1,75 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// A ROM's description of itself: a version byte, then text whose first
+/// line is the ROM's name
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metadata {
+    pub version: u8,
+    pub text: String,
+}
+
+impl Metadata {
+    /// Parse the block at `addr` in memory, the text running up to the first
+    /// null byte or the end of memory
+    pub fn read(mem: &[u8], addr: u16) -> Option<Self> {
+        let block = mem.get(addr as usize..)?;
+        let (&version, rest) = block.split_first()?;
+        let end = rest.iter().position(|&b| b == 0).unwrap_or(rest.len());
+        let text = String::from_utf8_lossy(&rest[..end]).into_owned();
+        Some(Self { version, text })
+    }
+
+    /// Find the metadata of a ROM without running it, if its reset vector
+    /// starts by registering some
+    pub fn from_rom(rom: &[u8]) -> Option<Self> {
+        // ;meta .System/metadata DEO2
+        let &[0xa0, high, low, 0x80, 0x06, 0x37, ..] = rom else {
+            return None;
+        };
+        // the ROM is loaded at 0x0100
+        let addr = u16::from_be_bytes([high, low]).checked_sub(0x0100)?;
+        Self::read(rom, addr)
+    }
+
+    /// Read a `.rom` file and find its metadata
+    pub fn from_file(path: impl AsRef<Path>) -> io::Result<Option<Self>> {
+        Ok(Self::from_rom(&fs::read(path)?))
+    }
+
+    /// The first line of the text
+    pub fn name(&self) -> &str {
+        self.text.lines().next().unwrap_or_default()
+    }
+
+    /// Everything after the first line
+    pub fn description(&self) -> &str {
+        self.text.split_once('\n').map_or("", |(_, rest)| rest)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// `;meta .System/metadata DEO2 BRK @meta 00 "Demo 0a "A 20 "demo 00`
+    const ROM: [u8; 23] = [
+        0xa0, 0x01, 0x07, 0x80, 0x06, 0x37, 0x00, 0x00, b'D', b'e', b'm', b'o', b'\n', b'A', b' ',
+        b'd', b'e', b'm', b'o', 0x00, 0xff, 0xff, 0xff,
+    ];
+
+    #[test]
+    fn metadata_from_rom() {
+        let metadata = Metadata::from_rom(&ROM).unwrap();
+        assert_eq!(metadata.version, 0x00);
+        assert_eq!(metadata.name(), "Demo");
+        assert_eq!(metadata.description(), "A demo");
+    }
+
+    #[test]
+    fn rom_without_metadata() {
+        assert_eq!(Metadata::from_rom(&ROM[3..]), None);
+        assert_eq!(Metadata::from_rom(&[0xa0, 0x01]), None);
+    }
+}
@@ -0,0 +1,113 @@
+use super::error::EmuError;
+use super::varvara::{read_short, Device};
+
+/// Buttons on the Mouse device
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MouseButton {
+    Left,
+    Middle,
+    Right,
+}
+
+impl MouseButton {
+    /// Bit of the button in Mouse/state
+    pub fn mask(self) -> u8 {
+        match self {
+            MouseButton::Left => 0x01,
+            MouseButton::Middle => 0x02,
+            MouseButton::Right => 0x04,
+        }
+    }
+}
+
+/// The Mouse device. Like the Controller, it keeps the pointer, buttons
+/// and scroll that frontend events update through `Varvara::handle`, and the
+/// ROM only writes the vector.
+#[derive(Default)]
+pub struct Mouse {
+    x: u16,
+    y: u16,
+    buttons: u8,
+    scroll_x: i16,
+    scroll_y: i16,
+}
+
+impl Mouse {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn move_to(&mut self, x: u16, y: u16) {
+        self.x = x;
+        self.y = y;
+    }
+
+    pub fn press(&mut self, button: MouseButton) {
+        self.buttons |= button.mask();
+    }
+
+    pub fn release(&mut self, button: MouseButton) {
+        self.buttons &= !button.mask();
+    }
+
+    /// Set the scroll read from Mouse/scrollx and scrolly, or 0 once it's
+    /// been handled
+    pub fn scroll(&mut self, x: i16, y: i16) {
+        self.scroll_x = x;
+        self.scroll_y = y;
+    }
+}
+
+impl Device for Mouse {
+    fn dei(&mut self, io: &[u8], addr: u8) -> Result<u8, EmuError> {
+        let port = addr & 0x0F;
+        match port {
+            0x2..=0x5 | 0xa..=0xd => {
+                let short = self.dei2(io, addr & 0xFE)?;
+                let [high, low] = short.to_be_bytes();
+                Ok(if port & 0x1 == 0 { high } else { low })
+            }
+            0x6 => Ok(self.buttons),
+            _ => Ok(io[addr as usize]),
+        }
+    }
+
+    fn dei2(&mut self, io: &[u8], addr: u8) -> Result<u16, EmuError> {
+        let port = addr & 0x0F;
+        match port {
+            0x2 => Ok(self.x),
+            0x4 => Ok(self.y),
+            0xa => Ok(self.scroll_x as u16),
+            0xc => Ok(self.scroll_y as u16),
+            _ => Ok(read_short(io, addr)),
+        }
+    }
+
+    fn deo(
+        &mut self,
+        _io: &mut [u8],
+        _main: &mut [u8],
+        addr: u8,
+        _byte: u8,
+    ) -> Result<(), EmuError> {
+        let port = addr & 0x0F;
+        match port {
+            0x0..0xe => Ok(()),
+            _ => Err(EmuError::UnknownPort(addr)),
+        }
+    }
+
+    fn deo2(
+        &mut self,
+        _io: &mut [u8],
+        _main: &mut [u8],
+        addr: u8,
+        _short: u16,
+    ) -> Result<(), EmuError> {
+        let port = addr & 0x0F;
+        match port {
+            0x0 | 0x2 | 0x4 | 0xa | 0xc => Ok(()),
+            _ => Err(EmuError::UnknownPort(addr)),
+        }
+    }
+}
@@ -1,4 +1,10 @@
-pub const BASE_OPCODES: [&str; 7] = ["BRK", "INC", "DEO", "DEI", "ADD", "SUB", "LIT"];
+use super::error::EmuError;
+
+pub const BASE_OPCODES: [&str; 33] = [
+    "BRK", "INC", "POP", "NIP", "SWP", "ROT", "DUP", "OVR", "EQU", "NEQ", "GTH", "LTH", "JMP",
+    "JCN", "JSR", "STH", "LDZ", "STZ", "LDR", "STR", "LDA", "STA", "DEI", "DEO", "ADD", "SUB",
+    "MUL", "DIV", "AND", "ORA", "EOR", "SFT", "LIT",
+];
 
 pub struct CodeFlags {
     pub keep: bool,
@@ -13,53 +19,203 @@ pub struct LitFlags {
 
 pub enum Code {
     BRK,
+    JCI,
+    JMI,
+    JSI,
     INC(CodeFlags),
-    DEO(CodeFlags),
+    POP(CodeFlags),
+    NIP(CodeFlags),
+    SWP(CodeFlags),
+    ROT(CodeFlags),
+    DUP(CodeFlags),
+    OVR(CodeFlags),
+    EQU(CodeFlags),
+    NEQ(CodeFlags),
+    GTH(CodeFlags),
+    LTH(CodeFlags),
+    JMP(CodeFlags),
+    JCN(CodeFlags),
+    JSR(CodeFlags),
+    STH(CodeFlags),
+    LDZ(CodeFlags),
+    STZ(CodeFlags),
+    LDR(CodeFlags),
+    STR(CodeFlags),
+    LDA(CodeFlags),
+    STA(CodeFlags),
     DEI(CodeFlags),
+    DEO(CodeFlags),
     ADD(CodeFlags),
     SUB(CodeFlags),
+    MUL(CodeFlags),
+    DIV(CodeFlags),
+    AND(CodeFlags),
+    ORA(CodeFlags),
+    EOR(CodeFlags),
+    SFT(CodeFlags),
     LIT(LitFlags),
 }
 
+impl Code {
+    /// Mode flags of the operation, if it takes any
+    pub fn flags(&self) -> Option<&CodeFlags> {
+        match self {
+            Code::BRK | Code::JCI | Code::JMI | Code::JSI | Code::LIT(_) => None,
+            Code::INC(f)
+            | Code::POP(f)
+            | Code::NIP(f)
+            | Code::SWP(f)
+            | Code::ROT(f)
+            | Code::DUP(f)
+            | Code::OVR(f)
+            | Code::EQU(f)
+            | Code::NEQ(f)
+            | Code::GTH(f)
+            | Code::LTH(f)
+            | Code::JMP(f)
+            | Code::JCN(f)
+            | Code::JSR(f)
+            | Code::STH(f)
+            | Code::LDZ(f)
+            | Code::STZ(f)
+            | Code::LDR(f)
+            | Code::STR(f)
+            | Code::LDA(f)
+            | Code::STA(f)
+            | Code::DEI(f)
+            | Code::DEO(f)
+            | Code::ADD(f)
+            | Code::SUB(f)
+            | Code::MUL(f)
+            | Code::DIV(f)
+            | Code::AND(f)
+            | Code::ORA(f)
+            | Code::EOR(f)
+            | Code::SFT(f) => Some(f),
+        }
+    }
+}
+
 pub fn encode_base_code(code: &str) -> u8 {
     match code {
         "BRK" => 0x00,
         "INC" => 0x01,
+        "POP" => 0x02,
+        "NIP" => 0x03,
+        "SWP" => 0x04,
+        "ROT" => 0x05,
+        "DUP" => 0x06,
+        "OVR" => 0x07,
+        "EQU" => 0x08,
+        "NEQ" => 0x09,
+        "GTH" => 0x0a,
+        "LTH" => 0x0b,
+        "JMP" => 0x0c,
+        "JCN" => 0x0d,
+        "JSR" => 0x0e,
+        "STH" => 0x0f,
+        "LDZ" => 0x10,
+        "STZ" => 0x11,
+        "LDR" => 0x12,
+        "STR" => 0x13,
+        "LDA" => 0x14,
+        "STA" => 0x15,
         "DEI" => 0x16,
         "DEO" => 0x17,
         "ADD" => 0x18,
         "SUB" => 0x19,
+        "MUL" => 0x1a,
+        "DIV" => 0x1b,
+        "AND" => 0x1c,
+        "ORA" => 0x1d,
+        "EOR" => 0x1e,
+        "SFT" => 0x1f,
         "LIT" => 0x80,
         _ => panic!("Unrecognized opcode: {code}"),
     }
 }
 
-pub fn parse_code(byte: u8) -> Code {
+/// Mnemonic of a raw opcode byte, mode flags included, ie `ADD2k`
+pub fn name_code(byte: u8) -> String {
+    let code = 0b000_11111 & byte;
+    if code == 0x00 {
+        let name = match byte {
+            0x00 => "BRK",
+            0x20 => "JCI",
+            0x40 => "JMI",
+            0x60 => "JSI",
+            0x80 => "LIT",
+            0xa0 => "LIT2",
+            0xc0 => "LITr",
+            _ => "LIT2r",
+        };
+        return name.to_string();
+    }
+    let mut name = BASE_OPCODES[code as usize].to_string();
+    if 0b001_00000 & byte != 0 {
+        name.push('2');
+    }
+    if 0b100_00000 & byte != 0 {
+        name.push('k');
+    }
+    if 0b010_00000 & byte != 0 {
+        name.push('r');
+    }
+    name
+}
+
+pub fn parse_code(byte: u8) -> Result<Code, EmuError> {
     let code = 0b000_11111 & byte;
     let short = 0b001_00000 & byte != 0;
     let ret = 0b010_00000 & byte != 0;
-    if ret {
-        todo!("Return flag not yet implemented! Code: {byte:0>2x?}");
-    }
     let keep = 0b100_00000 & byte != 0;
-    if keep && code != 0x00 {
-        todo!("Keep flag not yet implemented! Code: {byte:0>2x?}");
-    }
 
     let flags = CodeFlags { keep, ret, short };
-    match code {
+    let code = match code {
         0x00 => {
             if keep {
                 Code::LIT(LitFlags { ret, short })
             } else {
-                Code::BRK
+                match (short, ret) {
+                    (false, false) => Code::BRK,
+                    (true, false) => Code::JCI,
+                    (false, true) => Code::JMI,
+                    (true, true) => Code::JSI,
+                }
             }
         }
         0x01 => Code::INC(flags),
+        0x02 => Code::POP(flags),
+        0x03 => Code::NIP(flags),
+        0x04 => Code::SWP(flags),
+        0x05 => Code::ROT(flags),
+        0x06 => Code::DUP(flags),
+        0x07 => Code::OVR(flags),
+        0x08 => Code::EQU(flags),
+        0x09 => Code::NEQ(flags),
+        0x0a => Code::GTH(flags),
+        0x0b => Code::LTH(flags),
+        0x0c => Code::JMP(flags),
+        0x0d => Code::JCN(flags),
+        0x0e => Code::JSR(flags),
+        0x0f => Code::STH(flags),
+        0x10 => Code::LDZ(flags),
+        0x11 => Code::STZ(flags),
+        0x12 => Code::LDR(flags),
+        0x13 => Code::STR(flags),
+        0x14 => Code::LDA(flags),
+        0x15 => Code::STA(flags),
         0x16 => Code::DEI(flags),
         0x17 => Code::DEO(flags),
         0x18 => Code::ADD(flags),
         0x19 => Code::SUB(flags),
-        _ => todo!("Missing opcode! Code: {byte:0>2x?}"),
-    }
+        0x1a => Code::MUL(flags),
+        0x1b => Code::DIV(flags),
+        0x1c => Code::AND(flags),
+        0x1d => Code::ORA(flags),
+        0x1e => Code::EOR(flags),
+        0x1f => Code::SFT(flags),
+        _ => return Err(EmuError::UnimplementedOpcode(byte)),
+    };
+    Ok(code)
 }
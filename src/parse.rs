@@ -1,7 +1,7 @@
 use std::collections::HashMap;
-use winnow::combinator::{alt, delimited, dispatch, fail, repeat, separated};
+use winnow::combinator::{alt, delimited, dispatch, eof, fail, not, peek, repeat, separated};
 use winnow::stream::AsChar;
-use winnow::token::{any, one_of, take_till, take_until, take_while};
+use winnow::token::{any, none_of, one_of, take_till, take_until, take_while};
 use winnow::{PResult, Parser, Stateful};
 
 use crate::opcode::{encode_base_code, BASE_OPCODES};
@@ -15,6 +15,9 @@ pub enum ROMItem<'s> {
     ZeroAddr(&'s str),
     ZeroSubAddr(&'s str, &'s str),
     SubAddr(&'s str, &'s str),
+    // immediate opcode (JCI, JMI or JSI) followed by a relative address
+    ImmAddr(u8, &'s str),
+    ImmSubAddr(u8, &'s str, &'s str),
     MacroDef(&'s str, Vec<ROMItem<'s>>),
     Macro(&'s str),
     AbsPad(u8, u8),
@@ -84,6 +87,7 @@ fn next_tokens<'s>(input: &mut Stream<'s>) -> PResult<Vec<ROMItem<'s>>> {
         parse_opcode,
         parse_many_hexbytes,
         parse_macro_call,
+        parse_label_call,
     ))
     .parse_next(input)?;
     Ok(out)
@@ -105,6 +109,8 @@ fn parse_rune<'s>(input: &mut Stream<'s>) -> PResult<Vec<ROMItem<'s>>> {
         ',' => parse_todo,
         ';' => abs_addr_rune,
         ':' => parse_todo,
+        '?' => cond_jump_rune,
+        '!' => jump_rune,
         '\'' => parse_todo,
         '"' => parse_todo,
         _ => fail::<_, Vec<ROMItem>, _>,
@@ -162,6 +168,39 @@ fn sub_abs_addr_rune<'s>(input: &mut Stream<'s>) -> PResult<Vec<ROMItem<'s>>> {
     Ok(vec![ROMItem::SubAddr(parent, child)])
 }
 
+fn cond_jump_rune<'s>(input: &mut Stream<'s>) -> PResult<Vec<ROMItem<'s>>> {
+    imm_addr(0x20).parse_next(input)
+}
+
+fn jump_rune<'s>(input: &mut Stream<'s>) -> PResult<Vec<ROMItem<'s>>> {
+    imm_addr(0x40).parse_next(input)
+}
+
+fn parse_label_call<'s>(input: &mut Stream<'s>) -> PResult<Vec<ROMItem<'s>>> {
+    // a rune that failed to parse, or a closing brace ending a macro body, is
+    // not a call
+    peek(none_of(NOT_A_CALL)).parse_next(input)?;
+    // neither is an opcode run into other characters, like SUB2abc or a0BRK
+    not((take_while(0.., HEX_DIGITS), parse_base_opcode)).parse_next(input)?;
+    imm_addr(0x60).parse_next(input)
+}
+
+const NOT_A_CALL: [char; 18] = [
+    '%', '|', '$', '@', '&', '#', '.', ',', ';', ':', '?', '!', '\'', '"', '{', '}', '(', ')',
+];
+
+fn imm_addr<'s>(opcode: u8) -> impl FnMut(&mut Stream<'s>) -> PResult<Vec<ROMItem<'s>>> {
+    move |input: &mut Stream<'s>| {
+        let addr = alt((sub_abs_addr_rune, abs_addr_rune_parent)).parse_next(input)?;
+        let out = match addr[0] {
+            ROMItem::Addr(p) => ROMItem::ImmAddr(opcode, p),
+            ROMItem::SubAddr(p, c) => ROMItem::ImmSubAddr(opcode, p, c),
+            _ => panic!("Should never reach here."),
+        };
+        Ok(vec![out])
+    }
+}
+
 fn rel_pad_rune<'s>(input: &mut Stream<'s>) -> PResult<Vec<ROMItem<'s>>> {
     let addr =
         alt((rel_pad_rune_short, rel_pad_rune_byte, rel_pad_rune_nibble)).parse_next(input)?;
@@ -222,6 +261,15 @@ fn take_whitespace0<'s>(input: &mut Stream<'s>) -> PResult<&'s str> {
     take_while(0.., (AsChar::is_space, AsChar::is_newline, '[', ']')).parse_next(input)
 }
 
+/// Succeeds without consuming anything when the current token has ended
+fn token_end<'s>(input: &mut Stream<'s>) -> PResult<()> {
+    alt((
+        eof.void(),
+        peek(one_of((AsChar::is_space, AsChar::is_newline, '[', ']'))).void(),
+    ))
+    .parse_next(input)
+}
+
 fn parse_comment<'s>(input: &mut Stream<'s>) -> PResult<Vec<ROMItem<'s>>> {
     ('(', take_until(0.., ')'), ')').parse_next(input)?;
     Ok(vec![])
@@ -273,6 +321,7 @@ fn parse_keep_flag(input: &mut Stream<'_>) -> PResult<bool> {
 
 fn parse_opcode<'s>(input: &mut Stream<'s>) -> PResult<Vec<ROMItem<'s>>> {
     let (base, flags) = (calculate_base_opcode, calculate_flags).parse_next(input)?;
+    token_end.parse_next(input)?;
     // if base & flags != 0, that's when we return an error
     // because it means an invalid flag has been used
     // ie LITk
@@ -324,7 +373,9 @@ fn parse_hexbyte<'s>(input: &mut Stream<'s>) -> PResult<ROMItem<'s>> {
 }
 
 fn parse_many_hexbytes<'s>(input: &mut Stream<'s>) -> PResult<Vec<ROMItem<'s>>> {
-    repeat(1.., parse_hexbyte).parse_next(input)
+    let bytes = repeat(1.., parse_hexbyte).parse_next(input)?;
+    token_end.parse_next(input)?;
+    Ok(bytes)
 }
 
 // TODO use an "in sequence" combinator?
@@ -384,6 +435,29 @@ mod test {
         assert_eq!(output, vec!(ROMItem::Byte(0x39)));
     }
 
+    #[test]
+    fn parses_every_base_opcode() {
+        let input = "BRK INC POP NIP SWP ROT DUP OVR EQU NEQ GTH LTH JMP JCN JSR STH \
+                     LDZ STZ LDR STR LDA STA DEI DEO ADD SUB MUL DIV AND ORA EOR SFT";
+        let state = State(HashMap::new());
+        let stream = Stream { input, state };
+
+        let output = parse_tal.parse(stream).unwrap();
+
+        assert_eq!(output, (0x00..=0x1f).map(ROMItem::Byte).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn parses_opcode_with_all_flags() {
+        let input = "JSR2kr ;on-frame";
+        let state = State(HashMap::new());
+        let mut stream = Stream { input, state };
+
+        let output = parse_opcode.parse_next(&mut stream).unwrap();
+
+        assert_eq!(output, vec!(ROMItem::Byte(0xee)));
+    }
+
     #[test]
     fn parses_flags() {
         let input = "2k ;on-frame";
@@ -566,6 +640,81 @@ mod test {
         assert!(output.is_err());
     }
 
+    #[test]
+    fn jump_runes() {
+        let input = "?loop !parent/child";
+        let state = State(HashMap::new());
+        let stream = Stream { input, state };
+
+        let output = parse_tal.parse(stream).unwrap();
+
+        assert_eq!(
+            output,
+            vec![
+                ROMItem::ImmAddr(0x20, "loop"),
+                ROMItem::ImmSubAddr(0x40, "parent", "child")
+            ]
+        );
+    }
+
+    #[test]
+    fn bare_words_are_label_calls() {
+        let input = "routine add-two parent/child";
+        let state = State(HashMap::new());
+        let stream = Stream { input, state };
+
+        let output = parse_tal.parse(stream).unwrap();
+
+        assert_eq!(
+            output,
+            vec![
+                ROMItem::ImmAddr(0x60, "routine"),
+                ROMItem::ImmAddr(0x60, "add-two"),
+                ROMItem::ImmSubAddr(0x60, "parent", "child")
+            ]
+        );
+    }
+
+    #[test]
+    fn errs_on_opcode_with_unknown_flag() {
+        let input = "ADD3";
+        let state = State(HashMap::new());
+        let stream = Stream { input, state };
+
+        let output = parse_tal.parse(stream);
+
+        assert!(output.is_err());
+    }
+
+    #[test]
+    fn errs_on_malformed_runes() {
+        for input in ["#zz", "|xyz", "$q"] {
+            let state = State(HashMap::new());
+            let stream = Stream { input, state };
+
+            let output = parse_tal.parse(stream);
+
+            assert!(output.is_err(), "{input}");
+        }
+    }
+
+    #[test]
+    fn label_call_inside_macro() {
+        let input = "%CALL { routine } CALL";
+        let state = State(HashMap::new());
+        let stream = Stream { input, state };
+
+        let output = parse_tal.parse(stream).unwrap();
+
+        assert_eq!(
+            output,
+            vec![
+                ROMItem::MacroDef("CALL", vec![ROMItem::ImmAddr(0x60, "routine")]),
+                ROMItem::Macro("CALL")
+            ]
+        );
+    }
+
     #[test]
     fn lit_rune() {
         let input = "#10";
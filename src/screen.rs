@@ -1,44 +1,78 @@
-use super::varvara::{read_bytes, Device};
+use super::error::EmuError;
+use super::varvara::{read_bytes, read_short, Device};
 
 pub struct Screen {
     pub buffer: Vec<u8>,
+    width: u16,
+    height: u16,
     x: u16,
     y: u16,
     addr: u16,
 }
 
-const WIDTH: usize = 512;
-const HEIGHT: usize = 320;
+/// Size of the screen until the ROM asks for another
+pub const DEFAULT_WIDTH: usize = 512;
+pub const DEFAULT_HEIGHT: usize = 320;
 
 impl Screen {
     pub fn new() -> Self {
-        let buffer = vec![0; WIDTH * HEIGHT];
+        let buffer = vec![0; DEFAULT_WIDTH * DEFAULT_HEIGHT];
         Self {
             buffer,
+            width: DEFAULT_WIDTH as u16,
+            height: DEFAULT_HEIGHT as u16,
             x: 0,
             y: 0,
             addr: 0,
         }
     }
 
-    pub fn draw_pixel(&mut self, byte: u8) {
+    pub fn width(&self) -> usize {
+        self.width as usize
+    }
+
+    pub fn height(&self) -> usize {
+        self.height as usize
+    }
+
+    /// Swap the framebuffer for a blank one of a new size. Like the
+    /// reference emulator, sizes under 8 or from 0x800 up are ignored.
+    pub fn resize(&mut self, width: u16, height: u16) {
+        if !(8..0x800).contains(&width) || !(8..0x800).contains(&height) {
+            return;
+        }
+        self.width = width;
+        self.height = height;
+        self.buffer = vec![0; self.width() * self.height()];
+    }
+
+    pub fn draw_pixel(&mut self, byte: u8) -> Result<(), EmuError> {
         let color = 0b00000011 & byte;
-        let index = self.x as usize + WIDTH * self.y as usize;
-        self.buffer[index] = color;
+        self.put(self.x as usize, self.y as usize, color);
+        Ok(())
+    }
+
+    /// Set one pixel, silently clipping anything past the right or bottom
+    /// edge rather than wrapping it onto the next row
+    fn put(&mut self, x: usize, y: usize, color: u8) {
+        if x < self.width() && y < self.height() {
+            let width = self.width();
+            self.buffer[x + width * y] = color;
+        }
     }
 
-    pub fn draw_sprite(&mut self, byte: u8, mem: &[u8]) {
+    pub fn draw_sprite(&mut self, byte: u8, mem: &[u8]) -> Result<(), EmuError> {
         let mode = (byte & 0b1000_0000).count_ones();
         match mode {
             0 => self.draw_sprite_1bpp(byte, mem),
             1 => self.draw_sprite_2bpp(byte, mem),
             _ => panic!("binary violated"),
-        };
+        }
     }
 
-    fn draw_sprite_2bpp(&mut self, byte: u8, mem: &[u8]) {
-        let high_data = read_bytes(mem, self.addr + 8, 8);
-        let low_data = read_bytes(mem, self.addr, 8);
+    fn draw_sprite_2bpp(&mut self, byte: u8, mem: &[u8]) -> Result<(), EmuError> {
+        let high_data: [u8; 8] = read_bytes(mem, self.addr.wrapping_add(8));
+        let low_data: [u8; 8] = read_bytes(mem, self.addr);
         let color_set = byte & 0b0000_1111;
         let color = match color_set {
             0x0 => (0, 0, 1, 2), // TODO add transparency
@@ -60,59 +94,54 @@ impl Screen {
             _ => panic!("violated binary"),
         };
 
-        let index = self.x as usize + WIDTH * self.y as usize;
-        (0..8).for_each(|y| {
-            let mut pixel_mask = 0b10000000;
-            self.buffer[y * WIDTH + index..y * WIDTH + index + 8]
-                .iter_mut()
-                .for_each(|p| {
-                    let high = (pixel_mask & high_data[y]).count_ones();
-                    let low = (pixel_mask & low_data[y]).count_ones();
-                    let quad_color = match (high, low) {
-                        (0, 0) => color.0,
-                        (0, 1) => color.1,
-                        (1, 0) => color.2,
-                        (1, 1) => color.3,
-                        _ => panic!("binary math failed"),
-                    };
-                    if color.0 == color.1 && (high, low) == (0, 0) {
-                        // transparent background
-                        // TODO test this
-                    } else {
-                        *p = quad_color;
-                    }
-                    pixel_mask >>= 1;
-                });
-        });
-    }
-
-    fn draw_sprite_1bpp(&mut self, byte: u8, mem: &[u8]) {
-        let sprite_data = read_bytes(mem, self.addr, 8);
+        let (x, y) = (self.x as usize, self.y as usize);
+        for row in 0..8 {
+            for col in 0..8 {
+                let pixel_mask = 0b10000000 >> col;
+                let high = (pixel_mask & high_data[row]).count_ones();
+                let low = (pixel_mask & low_data[row]).count_ones();
+                let quad_color = match (high, low) {
+                    (0, 0) => color.0,
+                    (0, 1) => color.1,
+                    (1, 0) => color.2,
+                    (1, 1) => color.3,
+                    _ => panic!("binary math failed"),
+                };
+                if color.0 == color.1 && (high, low) == (0, 0) {
+                    // transparent background
+                    // TODO test this
+                } else {
+                    self.put(x + col, y + row, quad_color);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn draw_sprite_1bpp(&mut self, byte: u8, mem: &[u8]) -> Result<(), EmuError> {
+        let sprite_data: [u8; 8] = read_bytes(mem, self.addr);
         let _layer = byte & 0b0100_0000;
         let _flip_y = byte & 0b0010_0000;
         let _flip_x = byte & 0b0001_0000;
         let fg_color = byte & 0b0000_0011;
         let bg_color = (byte & 0b0000_1100) >> 2;
-        let index = self.x as usize + WIDTH * self.y as usize;
-        (0..8).for_each(|y| {
-            let mut pixel_mask = 0b10000000;
-            self.buffer[y * WIDTH + index..y * WIDTH + index + 8]
-                .iter_mut()
-                .for_each(|p| {
-                    let pixel = pixel_mask & sprite_data[y];
-                    let color = match pixel.count_ones() {
-                        0 => bg_color,
-                        1 => fg_color,
-                        _ => panic!("binary math failed"),
-                    };
-                    if bg_color == fg_color && pixel.count_ones() == 0 {
-                        // transparent background
-                    } else {
-                        *p = color;
-                    }
-                    pixel_mask >>= 1;
-                });
-        });
+        let (x, y) = (self.x as usize, self.y as usize);
+        for (row, data) in sprite_data.iter().enumerate() {
+            for col in 0..8 {
+                let pixel = (0b10000000 >> col) & data;
+                let color = match pixel.count_ones() {
+                    0 => bg_color,
+                    1 => fg_color,
+                    _ => panic!("binary math failed"),
+                };
+                if bg_color == fg_color && pixel.count_ones() == 0 {
+                    // transparent background
+                } else {
+                    self.put(x + col, y + row, color);
+                }
+            }
+        }
+        Ok(())
     }
 }
 
@@ -123,22 +152,225 @@ impl Default for Screen {
 }
 
 impl Device for Screen {
-    fn notify_deo(&mut self, _io: &[u8], main: &[u8], addr: u8, byte: u8) {
+    fn dei(&mut self, io: &[u8], addr: u8) -> Result<u8, EmuError> {
         let port = addr & 0x0F;
         match port {
-            0xe => self.draw_pixel(byte),
-            0xf => self.draw_sprite(byte, main),
-            _ => panic!("Don't know how to write to port {port}!"),
+            0x2..=0x5 => {
+                let short = self.dei2(io, addr & 0xFE)?;
+                let [high, low] = short.to_be_bytes();
+                Ok(if port & 0x1 == 0 { high } else { low })
+            }
+            _ => Ok(io[addr as usize]),
+        }
+    }
+
+    fn dei2(&mut self, io: &[u8], addr: u8) -> Result<u16, EmuError> {
+        let port = addr & 0x0F;
+        match port {
+            0x2 => Ok(self.width),
+            0x4 => Ok(self.height),
+            _ => Ok(read_short(io, addr)),
+        }
+    }
+
+    fn deo(&mut self, _io: &mut [u8], main: &mut [u8], addr: u8, byte: u8) -> Result<(), EmuError> {
+        let port = addr & 0x0F;
+        match port {
+            // the vector is only ever read back, and auto isn't supported yet
+            0x0 | 0x1 | 0x6 => {}
+            0x2 | 0x3 => self.resize(with_half(self.width, port, byte), self.height),
+            0x4 | 0x5 => self.resize(self.width, with_half(self.height, port, byte)),
+            0x8 | 0x9 => self.x = with_half(self.x, port, byte),
+            0xa | 0xb => self.y = with_half(self.y, port, byte),
+            0xc | 0xd => self.addr = with_half(self.addr, port, byte),
+            0xe => return self.draw_pixel(byte),
+            0xf => return self.draw_sprite(byte, main),
+            _ => return Err(EmuError::UnknownPort(addr)),
         };
+        Ok(())
     }
 
-    fn notify_deo2(&mut self, _io: &[u8], _main: &[u8], addr: u8, short: u16) {
+    fn deo2(
+        &mut self,
+        _io: &mut [u8],
+        _main: &mut [u8],
+        addr: u8,
+        short: u16,
+    ) -> Result<(), EmuError> {
         let port = addr & 0x0F;
         match port {
+            0x0 | 0x6 => {}
+            0x2 => self.resize(short, self.height),
+            0x4 => self.resize(self.width, short),
             0x8 => self.x = short,
             0xa => self.y = short,
             0xc => self.addr = short,
-            _ => panic!("Don't know how to write to port {port}!"),
+            _ => return Err(EmuError::UnknownPort(addr)),
         };
+        Ok(())
+    }
+}
+
+/// `short` with the byte written to `port` in place of its matching half,
+/// the high byte for an even port and the low byte for an odd one
+fn with_half(short: u16, port: u8, byte: u8) -> u16 {
+    let [high, low] = short.to_be_bytes();
+    if port & 0x1 == 0 {
+        u16::from_be_bytes([byte, low])
+    } else {
+        u16::from_be_bytes([high, byte])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn pixel_past_right_edge_is_clipped() {
+        let mut screen = Screen::new();
+        let mut io = [0; 0x100];
+        let mut main = [0; 0x100];
+        screen
+            .deo2(&mut io, &mut main, 0x28, DEFAULT_WIDTH as u16)
+            .unwrap();
+
+        screen.deo(&mut io, &mut main, 0x2e, 0x01).unwrap();
+
+        // doesn't wrap onto the start of the next row
+        assert!(screen.buffer.iter().all(|&p| p == 0));
+    }
+
+    #[test]
+    fn pixel_past_bottom_edge_is_clipped() {
+        let mut screen = Screen::new();
+        let mut io = [0; 0x100];
+        let mut main = [0; 0x100];
+        screen.deo2(&mut io, &mut main, 0x2a, 0x8000).unwrap();
+
+        screen.deo(&mut io, &mut main, 0x2e, 0x01).unwrap();
+
+        assert!(screen.buffer.iter().all(|&p| p == 0));
+    }
+
+    #[test]
+    fn sprite_on_the_edge_is_clipped() {
+        let mut screen = Screen::new();
+        screen.resize(16, 16);
+        let mut io = [0; 0x100];
+        let mut main = [0xff; 0x100];
+        screen.deo2(&mut io, &mut main, 0x28, 12).unwrap();
+        screen.deo2(&mut io, &mut main, 0x2a, 12).unwrap();
+
+        screen.deo(&mut io, &mut main, 0x2f, 0x01).unwrap();
+
+        let drawn: Vec<(usize, usize)> = (0..16 * 16)
+            .filter(|i| screen.buffer[*i] == 0x01)
+            .map(|i| (i % 16, i / 16))
+            .collect();
+        assert_eq!(drawn.len(), 4 * 4);
+        assert!(drawn.iter().all(|&(x, y)| x >= 12 && y >= 12));
+    }
+
+    #[test]
+    fn sprite_data_wraps_past_end_of_memory() {
+        let mut screen = Screen::new();
+        screen.resize(16, 16);
+        let mut io = [0; 0x100];
+        let mut main = [0; 0x10000];
+        main[0xfffc..].copy_from_slice(&[0xff; 4]);
+        main[..4].copy_from_slice(&[0x80; 4]);
+        screen.deo2(&mut io, &mut main, 0x2c, 0xfffc).unwrap();
+
+        screen.deo(&mut io, &mut main, 0x2f, 0x01).unwrap();
+
+        // the last four rows come from the start of memory
+        assert!(screen.buffer[..4 * 16]
+            .chunks(16)
+            .all(|row| row[..8] == [1; 8]));
+        assert!(screen.buffer[4 * 16..8 * 16]
+            .chunks(16)
+            .all(|row| row[..8] == [1, 0, 0, 0, 0, 0, 0, 0]));
+    }
+
+    #[test]
+    fn resize_reports_new_size() {
+        let mut screen = Screen::new();
+        let mut io = [0; 0x100];
+        let mut main = [0; 0x100];
+        assert_eq!(screen.dei2(&io, 0x22), Ok(DEFAULT_WIDTH as u16));
+
+        screen.deo2(&mut io, &mut main, 0x22, 0x0100).unwrap();
+        screen.deo2(&mut io, &mut main, 0x24, 0x0020).unwrap();
+
+        assert_eq!(screen.dei2(&io, 0x22), Ok(0x0100));
+        assert_eq!(screen.dei(&io, 0x24), Ok(0x00));
+        assert_eq!(screen.dei(&io, 0x25), Ok(0x20));
+        assert_eq!(screen.buffer.len(), 0x100 * 0x20);
+    }
+
+    #[test]
+    fn resize_ignores_sizes_out_of_range() {
+        let mut screen = Screen::new();
+        let mut io = [0; 0x100];
+        let mut main = [0; 0x100];
+
+        screen.deo2(&mut io, &mut main, 0x22, 0xffff).unwrap();
+        screen.deo2(&mut io, &mut main, 0x24, 0x0800).unwrap();
+        screen.deo2(&mut io, &mut main, 0x22, 0x0007).unwrap();
+        screen.resize(0, 0);
+
+        assert_eq!(screen.dei2(&io, 0x22), Ok(DEFAULT_WIDTH as u16));
+        assert_eq!(screen.dei2(&io, 0x24), Ok(DEFAULT_HEIGHT as u16));
+        assert_eq!(screen.buffer.len(), DEFAULT_WIDTH * DEFAULT_HEIGHT);
+
+        screen.deo2(&mut io, &mut main, 0x24, 0x07ff).unwrap();
+        assert_eq!(screen.dei2(&io, 0x24), Ok(0x07ff));
+    }
+
+    #[test]
+    fn pixels_land_by_current_width() {
+        let mut screen = Screen::new();
+        let mut io = [0; 0x100];
+        let mut main = [0; 0x100];
+        screen.resize(16, 16);
+        screen.deo2(&mut io, &mut main, 0x28, 3).unwrap();
+        screen.deo2(&mut io, &mut main, 0x2a, 2).unwrap();
+
+        screen.deo(&mut io, &mut main, 0x2e, 0x02).unwrap();
+
+        assert_eq!(screen.buffer[2 * 16 + 3], 0x02);
+    }
+
+    #[test]
+    fn byte_writes_set_each_half() {
+        let mut screen = Screen::new();
+        let mut io = [0; 0x100];
+        let mut main = [0; 0x100];
+
+        screen.deo(&mut io, &mut main, 0x22, 0x01).unwrap();
+        screen.deo(&mut io, &mut main, 0x25, 0x20).unwrap();
+        assert_eq!((screen.width(), screen.height()), (0x100, 0x120));
+
+        screen.deo(&mut io, &mut main, 0x29, 0x03).unwrap();
+        screen.deo(&mut io, &mut main, 0x2b, 0x02).unwrap();
+        screen.deo(&mut io, &mut main, 0x2e, 0x01).unwrap();
+        assert_eq!(screen.buffer[3 + 2 * 0x100], 0x01);
+
+        screen.deo(&mut io, &mut main, 0x2c, 0x12).unwrap();
+        screen.deo(&mut io, &mut main, 0x2d, 0x34).unwrap();
+        assert_eq!(screen.addr, 0x1234);
+    }
+
+    #[test]
+    fn unknown_port_is_an_error() {
+        let mut screen = Screen::new();
+        let mut io = [0; 0x100];
+        let mut main = [0; 0x100];
+
+        // 0x7, right after auto, is the one port the Screen leaves unused
+        let result = screen.deo(&mut io, &mut main, 0x27, 0x01);
+
+        assert_eq!(result, Err(EmuError::UnknownPort(0x27)));
     }
 }
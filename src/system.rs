@@ -1,21 +1,125 @@
-use std::io;
+use std::io::{self, Write};
 
-use super::varvara::Device;
+use super::cpu::Cpu;
+use super::error::EmuError;
+use super::varvara::{read_short, Device};
+
+/// Most pages of memory a machine can have, main memory included
+pub const MAX_PAGES: usize = 16;
 
 pub struct System {
-    _out: std::io::Stdout,
+    /// Where System/debug reports go
+    debug_out: Box<dyn Write>,
+    /// Whether the ROM asked for a report since the last one
+    debug_requested: bool,
+    /// Where the ROM said its metadata block is
+    metadata: Option<u16>,
     colors: [u32; 4],
+    /// Extra 64KiB pages after main memory, for expansion. Each is allocated
+    /// the first time an expansion command writes to it.
+    banks: Vec<Option<Box<[u8]>>>,
 }
 
 impl System {
     pub fn new() -> Self {
-        let out = std::io::stdout();
+        Self::with_debug_sink(Box::new(io::stderr()))
+    }
+
+    /// A System whose debug reports go to `debug_out` instead of stderr
+    pub fn with_debug_sink(debug_out: Box<dyn Write>) -> Self {
         let colors = [0xFFFFFF, 0x000000, 0x77ddbb, 0xff6622];
-        Self { _out: out, colors }
+        Self {
+            debug_out,
+            debug_requested: false,
+            metadata: None,
+            colors,
+            banks: vec![None; MAX_PAGES - 1],
+        }
+    }
+
+    /// Print both stacks if the ROM wrote to System/debug since the last call.
+    /// That is all the reference emulator prints: its debug port only calls
+    /// `system_inspect`, which writes one `WST` and one `RST` line, so a ROM's
+    /// debug output reads the same under either.
+    pub fn print_debug(&mut self, cpu: &Cpu) -> io::Result<()> {
+        if !std::mem::take(&mut self.debug_requested) {
+            return Ok(());
+        }
+        writeln!(self.debug_out, "WST {}", cpu.work.inspect())?;
+        writeln!(self.debug_out, "RST {}", cpu.ret.inspect())?;
+        self.debug_out.flush()
+    }
+
+    /// Give the machine `pages` pages of memory in all, main memory
+    /// included, up to `MAX_PAGES`
+    pub fn set_pages(&mut self, pages: usize) {
+        let extra = pages.clamp(1, MAX_PAGES) - 1;
+        self.banks.resize(extra, None);
+    }
+
+    /// Number of pages of memory, main memory included
+    pub fn pages(&self) -> usize {
+        1 + self.banks.len()
+    }
+
+    /// A page after main memory, once an expansion command has written to it
+    pub fn bank(&self, page: usize) -> Option<&[u8]> {
+        self.banks.get(page.checked_sub(1)?)?.as_deref()
+    }
+
+    /// Byte at an address in a page, page 0 being main memory
+    fn peek_paged(&self, main: &[u8], page: usize, addr: u16) -> u8 {
+        match page {
+            0 => main[addr as usize],
+            _ => self.bank(page).map_or(0, |bank| bank[addr as usize]),
+        }
+    }
+
+    fn poke_paged(&mut self, main: &mut [u8], page: usize, addr: u16, byte: u8) {
+        match page {
+            0 => main[addr as usize] = byte,
+            _ => {
+                let bank = self.banks[page - 1].get_or_insert_with(|| vec![0; 0x10000].into());
+                bank[addr as usize] = byte;
+            }
+        }
     }
 
-    fn _print_debug(&mut self) -> io::Result<()> {
-        todo!();
+    /// Run the System/expansion command block at `addr` in main memory.
+    /// Pages wrap around the memory the machine has, and addresses wrap
+    /// around within their page.
+    ///
+    /// - fill: `00 length page addr value`
+    /// - cpyl: `01 length src-page src-addr dst-page dst-addr`, low to high
+    /// - cpyr: `02 length src-page src-addr dst-page dst-addr`, high to low
+    fn expansion(&mut self, main: &mut [u8], addr: u16) {
+        let pages = self.pages();
+        let byte = |offset: u16| main[addr.wrapping_add(offset) as usize];
+        let arg = |offset: u16| u16::from_be_bytes([byte(offset), byte(offset + 1)]);
+        let page = |offset: u16| arg(offset) as usize % pages;
+        let length = arg(1);
+        let (src_page, src) = (page(3), arg(5));
+        let (dst_page, dst) = (page(7), arg(9));
+        let (op, value) = (byte(0), byte(7));
+        match op {
+            0x00 => {
+                for i in 0..length {
+                    self.poke_paged(main, src_page, src.wrapping_add(i), value);
+                }
+            }
+            0x01 | 0x02 => {
+                let mut copy = |i: u16| {
+                    let byte = self.peek_paged(main, src_page, src.wrapping_add(i));
+                    self.poke_paged(main, dst_page, dst.wrapping_add(i), byte);
+                };
+                if op == 0x01 {
+                    (0..length).for_each(&mut copy);
+                } else {
+                    (0..length).rev().for_each(&mut copy);
+                }
+            }
+            _ => {}
+        }
     }
 
     fn update_color(&mut self, io: &[u8]) {
@@ -25,6 +129,11 @@ impl System {
         self.colors = [0, 1, 2, 3].map(|i| shorts_to_0rgb(reds, greens, blues, i));
     }
 
+    /// Address of the ROM's metadata block in main memory, once registered
+    pub fn metadata_addr(&self) -> Option<u16> {
+        self.metadata
+    }
+
     pub fn index_to_0rgb(&self, color: u8) -> u32 {
         self.colors[color as usize]
     }
@@ -37,21 +146,45 @@ impl Default for System {
 }
 
 impl Device for System {
-    fn notify_deo(&mut self, _io: &[u8], _main: &[u8], addr: u8, _byte: u8) {
+    fn deo(&mut self, io: &mut [u8], main: &mut [u8], addr: u8, byte: u8) -> Result<(), EmuError> {
         let port = addr & 0x0F;
         match port {
-            0xe => todo!("debug port"),
-            0xf => todo!("state port"),
-            _ => panic!("Don't know how to write to port {port}!"),
-        };
+            // the vector and the stack pointers are only ever read back
+            0x0 | 0x1 | 0x2 | 0x4 | 0x5 => Ok(()),
+            // writing the low byte of the expansion address runs the command
+            0x3 => {
+                self.expansion(main, read_short(io, 0x02));
+                Ok(())
+            }
+            0x6 | 0x7 => {
+                self.metadata = Some(read_short(io, 0x06));
+                Ok(())
+            }
+            0x8..=0xd => {
+                self.update_color(io);
+                Ok(())
+            }
+            0xe => {
+                self.debug_requested |= byte != 0;
+                Ok(())
+            }
+            // 0xf, the state: Varvara stops running once it's non-zero
+            _ => Ok(()),
+        }
     }
 
-    fn notify_deo2(&mut self, io: &[u8], _main: &[u8], addr: u8, _short: u16) {
-        let port = addr & 0x0F;
-        match port {
-            0x7..0xe => self.update_color(io),
-            _ => panic!("Don't know how to do that yet"),
-        };
+    fn deo2(
+        &mut self,
+        io: &mut [u8],
+        main: &mut [u8],
+        addr: u8,
+        short: u16,
+    ) -> Result<(), EmuError> {
+        // the same as writing the two bytes, so a short to 0x02 runs the
+        // expansion through 0x03
+        let [high, low] = short.to_be_bytes();
+        self.deo(io, main, addr, high)?;
+        self.deo(io, main, addr + 1, low)
     }
 }
 
@@ -67,3 +200,93 @@ fn shorts_to_0rgb(reds: u16, greens: u16, blues: u16, index: usize) -> u32 {
     color |= blue << 4 | blue;
     color
 }
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::varvara::Varvara;
+
+    /// Debug output that the test can still read after handing it over
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn debug_port_prints_stacks() {
+        let mut varvara = Varvara::new();
+        let mut cpu = Cpu::new();
+        let out = Shared::default();
+        varvara.register(
+            0x0,
+            Box::new(System::with_debug_sink(Box::new(out.clone()))),
+        );
+        // #0f6f .System/r DEO2 #01 #02 #03 STH #01 .System/debug DEO BRK
+        let rom = [
+            0xa0, 0x0f, 0x6f, 0x80, 0x08, 0x37, 0x80, 0x01, 0x80, 0x02, 0x80, 0x03, 0x0f, 0x80,
+            0x01, 0x80, 0x0e, 0x17, 0x00,
+        ];
+        varvara.main[0x0100..0x0100 + rom.len()].copy_from_slice(&rom);
+        varvara.eval(&mut cpu, 0x0100).unwrap();
+
+        let report = String::from_utf8(out.0.borrow().clone()).unwrap();
+        assert_eq!(
+            report,
+            "WST 00 00 00 00 00 00|01 02 <\n\
+             RST 00 00 00 00 00 00 00|03 <\n"
+        );
+    }
+
+    #[test]
+    fn debug_port_is_quiet_for_zero() {
+        let mut varvara = Varvara::new();
+        let mut cpu = Cpu::new();
+        let out = Shared::default();
+        varvara.register(
+            0x0,
+            Box::new(System::with_debug_sink(Box::new(out.clone()))),
+        );
+        // #00 .System/debug DEO BRK
+        let rom = [0x80, 0x00, 0x80, 0x0e, 0x17, 0x00];
+        varvara.main[0x0100..0x0100 + rom.len()].copy_from_slice(&rom);
+        varvara.eval(&mut cpu, 0x0100).unwrap();
+
+        assert!(out.0.borrow().is_empty());
+    }
+
+    #[test]
+    fn every_port_takes_bytes() {
+        let mut varvara = Varvara::new();
+
+        for port in 0x00..=0x0f {
+            assert_eq!(varvara.deo(port, 0x00), Ok(()));
+        }
+    }
+
+    #[test]
+    fn expansion_runs_on_the_low_byte() {
+        let mut varvara = Varvara::new();
+        // fill 2 bytes of page 1 at 0x0010 with 0x55
+        varvara.main[0x0200..0x0208]
+            .copy_from_slice(&[0x00, 0x00, 0x02, 0x00, 0x01, 0x00, 0x10, 0x55]);
+
+        varvara.deo(0x02, 0x02).unwrap();
+        let system = varvara.device::<System>().unwrap();
+        assert_eq!(system.bank(1), None);
+
+        varvara.deo(0x03, 0x00).unwrap();
+        let system = varvara.device::<System>().unwrap();
+        assert_eq!(system.bank(1).unwrap()[0x10..0x12], [0x55; 2]);
+    }
+}
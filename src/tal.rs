@@ -21,8 +21,8 @@ pub fn assemble(input: &str, output: &str) -> std::io::Result<()> {
 
     let macros_applied = apply_macros(&parsed);
 
-    let mut mem: [u8; 0xffff] = [0; 0xffff];
-    let trimmed_mem = write(&macros_applied, &mut mem);
+    let mut mem: [u8; 0x10000] = [0; 0x10000];
+    let trimmed_mem = write(&macros_applied, &mut mem)?;
 
     std::fs::write(output, trimmed_mem)?;
 
@@ -45,6 +45,8 @@ fn resolve_locations<'s>(items: &'s [ROMItem]) -> HashMap<(&'s str, Option<&'s s
                 ROMItem::ZeroSubAddr(_, _) => *loc + 2, // ie #01
                 ROMItem::Addr(_) => *loc + 3,           // ie #0104
                 ROMItem::SubAddr(_, _) => *loc + 3,     // ie #0104
+                ROMItem::ImmAddr(_, _) => *loc + 3,     // ie 60 0004
+                ROMItem::ImmSubAddr(_, _, _) => *loc + 3,
                 ROMItem::AbsPad(a, b) => u16::from_be_bytes([*a, *b]),
                 ROMItem::RelPad(a, b) => *loc + u16::from_be_bytes([*a, *b]),
                 ROMItem::MacroDef(_, _) => todo!("No macros should exist at this point."),
@@ -69,11 +71,40 @@ fn resolve_locations<'s>(items: &'s [ROMItem]) -> HashMap<(&'s str, Option<&'s s
         .collect()
 }
 
-fn write<'a>(items: &[ROMItem], mem: &'a mut [u8; 0xffff]) -> &'a [u8] {
+/// Looks up a label, naming it in the error when it was never defined
+fn lookup(
+    locations: &HashMap<(&str, Option<&str>), u16>,
+    parent: &str,
+    child: Option<&str>,
+) -> std::io::Result<u16> {
+    locations.get(&(parent, child)).copied().ok_or_else(|| {
+        let name = match child {
+            Some(child) => format!("{parent}/{child}"),
+            None => parent.to_string(),
+        };
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("undefined label: {name}"),
+        )
+    })
+}
+
+fn write<'a>(items: &[ROMItem], mem: &'a mut [u8; 0x10000]) -> std::io::Result<&'a [u8]> {
     // TODO refactor out the writing procedure. Handle wrapping address math in a
     // way that protects from zero page writes.
     let locations = resolve_locations(items);
     let mut max_written = 0x0100;
+    for item in items {
+        match item {
+            ROMItem::ZeroAddr(name) | ROMItem::Addr(name) | ROMItem::ImmAddr(_, name) => {
+                lookup(&locations, name, None)?;
+            }
+            ROMItem::ZeroSubAddr(p, c) | ROMItem::SubAddr(p, c) | ROMItem::ImmSubAddr(_, p, c) => {
+                lookup(&locations, p, Some(c))?;
+            }
+            _ => {}
+        }
+    }
     items.iter().fold(0x0100, |i, item| match item {
         ROMItem::Byte(b) => {
             if i < 0x0100 {
@@ -133,12 +164,37 @@ fn write<'a>(items: &[ROMItem], mem: &'a mut [u8; 0xffff]) -> &'a [u8] {
             max_written = max(max_written, i + 2);
             i + 3
         }
+        ROMItem::ImmAddr(opcode, name) => {
+            if i < 0x0100 {
+                panic!("Can't write to zero page.")
+            };
+            mem[i as usize] = *opcode;
+            // relative to the address right after the immediate opcode
+            let offset = locations[&(*name, None)].wrapping_sub(i + 3);
+            let [a, b] = offset.to_be_bytes();
+            mem[i as usize + 1] = a;
+            mem[i as usize + 2] = b;
+            max_written = max(max_written, i + 2);
+            i + 3
+        }
+        ROMItem::ImmSubAddr(opcode, parent, child) => {
+            if i < 0x0100 {
+                panic!("Can't write to zero page.")
+            };
+            mem[i as usize] = *opcode;
+            let offset = locations[&(*parent, Some(*child))].wrapping_sub(i + 3);
+            let [a, b] = offset.to_be_bytes();
+            mem[i as usize + 1] = a;
+            mem[i as usize + 2] = b;
+            max_written = max(max_written, i + 2);
+            i + 3
+        }
         ROMItem::AbsPad(a, b) => u16::from_be_bytes([*a, *b]),
         ROMItem::RelPad(a, b) => i + u16::from_be_bytes([*a, *b]),
         ROMItem::MacroDef(_, _) => panic!("No macros should exist at this point."),
         ROMItem::Macro(_) => panic!("No macros should exist at this point."),
     });
-    &mem[0x0100..=max_written as usize]
+    Ok(&mem[0x0100..=max_written as usize])
 }
 
 fn apply_macros<'s>(items: &'s [ROMItem]) -> Vec<ROMItem<'s>> {
@@ -169,7 +225,7 @@ mod test {
         let in_macro = " #0008 ";
         let state = HashMap::new();
         let stream = Stream {
-            input: &in_macro,
+            input: in_macro,
             state: State(state),
         };
 
@@ -191,6 +247,33 @@ mod test {
         );
     }
 
+    #[test]
+    fn undefined_label_call_is_an_error() {
+        let input = "#01 #02 add-them ";
+        let stream = Stream {
+            input,
+            state: State(HashMap::new()),
+        };
+        let parsed: Vec<ROMItem> = parse_tal.parse(stream).unwrap();
+        let items = apply_macros(&parsed);
+
+        let mut mem: [u8; 0x10000] = [0; 0x10000];
+        let error = write(&items, &mut mem).unwrap_err();
+
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(error.to_string(), "undefined label: add-them");
+    }
+
+    #[test]
+    fn undefined_sublabel_is_named() {
+        let items = vec![ROMItem::Location("main"), ROMItem::SubAddr("main", "loop")];
+
+        let mut mem: [u8; 0x10000] = [0; 0x10000];
+        let error = write(&items, &mut mem).unwrap_err();
+
+        assert_eq!(error.to_string(), "undefined label: main/loop");
+    }
+
     #[test]
     fn read_one_location() {
         let items = vec![ROMItem::Byte(0x00), ROMItem::Location("test")];
@@ -208,8 +291,8 @@ mod test {
             ROMItem::Byte(0xff),
         ];
 
-        let mut mem: [u8; 0xffff] = [0; 0xffff];
-        let trimmed_mem = write(&items, &mut mem);
+        let mut mem: [u8; 0x10000] = [0; 0x10000];
+        let trimmed_mem = write(&items, &mut mem).unwrap();
 
         let desired = vec![0xa0, 0x01, 0x04, 0x00, 0xff];
         assert_eq!(trimmed_mem, desired);
@@ -225,8 +308,8 @@ mod test {
             ROMItem::Addr("label"),
         ];
 
-        let mut mem: [u8; 0xffff] = [0; 0xffff];
-        let trimmed_mem = write(&items, &mut mem);
+        let mut mem: [u8; 0x10000] = [0; 0x10000];
+        let trimmed_mem = write(&items, &mut mem).unwrap();
 
         let desired = vec![0xa0, 0x40, 0x01];
         assert_eq!(trimmed_mem, desired);
@@ -241,8 +324,8 @@ mod test {
             ROMItem::Addr("label"),
         ];
 
-        let mut mem: [u8; 0xffff] = [0; 0xffff];
-        let trimmed_mem = write(&items, &mut mem);
+        let mut mem: [u8; 0x10000] = [0; 0x10000];
+        let trimmed_mem = write(&items, &mut mem).unwrap();
 
         let desired = vec![0x00, 0x00, 0xa0, 0x01, 0x02];
         assert_eq!(trimmed_mem, desired);
@@ -254,7 +337,7 @@ mod test {
         // |00 10
         let items = vec![ROMItem::AbsPad(0x00, 0x00), ROMItem::Byte(10)];
 
-        let mut mem: [u8; 0xffff] = [0; 0xffff];
+        let mut mem: [u8; 0x10000] = [0; 0x10000];
         let _trimmed_mem = write(&items, &mut mem);
     }
 
@@ -268,8 +351,8 @@ mod test {
             ROMItem::Addr("label"),
         ];
 
-        let mut mem: [u8; 0xffff] = [0; 0xffff];
-        let trimmed_mem = write(&items, &mut mem);
+        let mut mem: [u8; 0x10000] = [0; 0x10000];
+        let trimmed_mem = write(&items, &mut mem).unwrap();
 
         let desired = vec![0x00, 0x00, 0x00, 0x00, 0xa0, 0x01, 0x04];
         assert_eq!(trimmed_mem, desired);
@@ -288,10 +371,39 @@ mod test {
             ROMItem::SubAddr("label", "a"),
         ];
 
-        let mut mem: [u8; 0xffff] = [0; 0xffff];
-        let trimmed_mem = write(&items, &mut mem);
+        let mut mem: [u8; 0x10000] = [0; 0x10000];
+        let trimmed_mem = write(&items, &mut mem).unwrap();
+
+        let desired = [0xa0, 0x00, 0x02];
+        assert_eq!(trimmed_mem, desired);
+    }
+
+    #[test]
+    fn imm_addr_forward() {
+        // |0100 !label BRK @label
+        let items = vec![
+            ROMItem::ImmAddr(0x40, "label"),
+            ROMItem::Byte(0x00),
+            ROMItem::Location("label"),
+        ];
+
+        let mut mem: [u8; 0x10000] = [0; 0x10000];
+        let trimmed_mem = write(&items, &mut mem).unwrap();
 
-        let desired = vec![0xa0, 0x00, 0x02];
+        let desired = [0x40, 0x00, 0x01, 0x00];
+        assert_eq!(trimmed_mem, desired);
+    }
+
+    #[test]
+    fn imm_addr_backward() {
+        // |0100 @loop ?loop
+        let items = vec![ROMItem::Location("loop"), ROMItem::ImmAddr(0x20, "loop")];
+
+        let mut mem: [u8; 0x10000] = [0; 0x10000];
+        let trimmed_mem = write(&items, &mut mem).unwrap();
+
+        let desired = [0x20, 0xff, 0xfd];
+        assert_eq!(trimmed_mem, desired);
     }
 
     #[test]
@@ -305,9 +417,10 @@ mod test {
             ROMItem::ZeroAddr("label"),
         ];
 
-        let mut mem: [u8; 0xffff] = [0; 0xffff];
-        let trimmed_mem = write(&items, &mut mem);
+        let mut mem: [u8; 0x10000] = [0; 0x10000];
+        let trimmed_mem = write(&items, &mut mem).unwrap();
 
-        let desired = vec![0x80, 0x10];
+        let desired = [0x80, 0x10];
+        assert_eq!(trimmed_mem, desired);
     }
 }
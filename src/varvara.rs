@@ -1,100 +1,375 @@
-use minifb::{Window, WindowOptions};
+use std::any::Any;
+use std::io::{BufReader, Read};
 
-use super::console::Console;
-use super::screen::Screen;
+use super::audio::{Audio, AudioSink, FRAME_SAMPLES};
+use super::console::{Console, InputType};
+use super::controller::Controller;
+use super::cpu::Cpu;
+use super::datetime::Datetime;
+use super::error::EmuError;
+use super::file::File;
+use super::frontend::{Event, Frontend};
+use super::metadata::Metadata;
+use super::mouse::Mouse;
+use super::screen::{Screen, DEFAULT_HEIGHT, DEFAULT_WIDTH};
 use super::system::System;
 
-pub trait Device {
-    fn notify_deo(&mut self, _io: &[u8], _main: &[u8], _port: u8, _value: u8) {}
-    fn notify_deo2(&mut self, _io: &[u8], _main: &[u8], _addr: u8, _short: u16) {}
+/// A peripheral plugged into one of the 16 device slots. Each slot owns 16
+/// ports of the device page, and `addr` is always the full address in it.
+pub trait Device: Any {
+    /// Read a byte from one of the device's ports. By default this is
+    /// whatever was last written there.
+    fn dei(&mut self, io: &[u8], addr: u8) -> Result<u8, EmuError> {
+        Ok(io[addr as usize])
+    }
+    fn dei2(&mut self, io: &[u8], addr: u8) -> Result<u16, EmuError> {
+        Ok(read_short(io, addr))
+    }
+    /// React to a byte written to one of the device's ports, which is
+    /// already stored in `io`
+    fn deo(
+        &mut self,
+        _io: &mut [u8],
+        _main: &mut [u8],
+        _addr: u8,
+        _byte: u8,
+    ) -> Result<(), EmuError> {
+        Ok(())
+    }
+    fn deo2(
+        &mut self,
+        _io: &mut [u8],
+        _main: &mut [u8],
+        _addr: u8,
+        _short: u16,
+    ) -> Result<(), EmuError> {
+        Ok(())
+    }
 }
 
 pub struct Varvara {
-    pub main: [u8; 0xFFFF],
-    pub io: [u8; 0xFF],
-    pub system: System,
-    pub console: Console,
-    pub screen: Screen,
-
-    pub window: Window,
+    pub main: [u8; 0x10000],
+    pub io: [u8; 0x100],
+    devices: [Option<Box<dyn Device>>; 16],
+    /// Where each frame's audio goes. Without one it is rendered and
+    /// dropped, so Audio vectors still fire on time.
+    audio_sink: Option<Box<dyn AudioSink>>,
 }
 
-const WIDTH: usize = 512;
-const HEIGHT: usize = 320;
-
 impl Varvara {
     pub fn new() -> Self {
-        let main = [0; 0xFFFF];
-        let io = [0; 0xFF];
-        let system = System::new();
-        let console = Console::new();
-        let screen = Screen::new();
-
-        let mut window = Window::new(
-            "Test - ESC to exit",
-            WIDTH,
-            HEIGHT,
-            WindowOptions {
-                scale: minifb::Scale::X4,
-                ..WindowOptions::default()
-            },
-        )
-        .unwrap_or_else(|e| {
-            panic!("{}", e);
-        });
-        window.set_target_fps(30);
-
-        Self {
+        let main = [0; 0x10000];
+        let io = [0; 0x100];
+
+        let mut varvara = Self {
             main,
             io,
-            system,
-            console,
-            screen,
-            window,
+            devices: Default::default(),
+            audio_sink: None,
+        };
+        varvara.register(0x0, Box::new(System::new()));
+        varvara.register(0x1, Box::new(Console::new()));
+        varvara.register(0x2, Box::new(Screen::new()));
+        for slot in 0x3..=0x6 {
+            varvara.register(slot, Box::new(Audio::new()));
+        }
+        varvara.register(0x8, Box::new(Controller::new()));
+        varvara.register(0x9, Box::new(Mouse::new()));
+        varvara.register(0xa, Box::new(File::new(".")));
+        varvara.register(0xb, Box::new(File::new(".")));
+        varvara.register(0xc, Box::new(Datetime::default()));
+        varvara
+    }
+
+    /// Give the machine `pages` pages of memory in all, main memory
+    /// included, up to `MAX_PAGES`. The extra pages belong to the System
+    /// device, which runs the expansion commands that reach them.
+    pub fn set_pages(&mut self, pages: usize) {
+        if let Some(system) = self.device_mut::<System>() {
+            system.set_pages(pages);
+        }
+    }
+
+    /// Number of pages of memory, main memory included
+    pub fn pages(&self) -> usize {
+        self.device::<System>().map_or(1, System::pages)
+    }
+
+    /// Plug a device into a slot (0x0 to 0xf), handing back whatever was
+    /// there before
+    pub fn register(&mut self, slot: u8, device: Box<dyn Device>) -> Option<Box<dyn Device>> {
+        self.devices[slot as usize].replace(device)
+    }
+
+    /// Unplug the device in a slot
+    pub fn unregister(&mut self, slot: u8) -> Option<Box<dyn Device>> {
+        self.devices[slot as usize].take()
+    }
+
+    /// The first registered device of type `T`
+    pub fn device<T: Device>(&self) -> Option<&T> {
+        self.devices
+            .iter()
+            .flatten()
+            .find_map(|device| (device.as_ref() as &dyn Any).downcast_ref())
+    }
+
+    /// The first registered device of type `T`, mutably
+    pub fn device_mut<T: Device>(&mut self) -> Option<&mut T> {
+        self.devices
+            .iter_mut()
+            .flatten()
+            .find_map(|device| (device.as_mut() as &mut dyn Any).downcast_mut())
+    }
+
+    /// The device in `slot`, if it is a `T`
+    fn slot_mut<T: Device>(&mut self, slot: u8) -> Option<&mut T> {
+        self.devices[slot as usize]
+            .as_mut()
+            .and_then(|device| (device.as_mut() as &mut dyn Any).downcast_mut())
+    }
+
+    /// Run the CPU from `addr` until it hits BRK, or the ROM halts the
+    /// machine
+    pub fn eval(&mut self, cpu: &mut Cpu, addr: u16) -> Result<(), EmuError> {
+        cpu.counter = addr;
+        while !self.halted() && !cpu.step(self)? {}
+        Ok(())
+    }
+
+    /// The metadata the ROM registered through System/metadata
+    pub fn metadata(&self) -> Option<Metadata> {
+        let addr = self.device::<System>()?.metadata_addr()?;
+        Metadata::read(&self.main, addr)
+    }
+
+    /// Print the stacks if the ROM asked for it through System/debug
+    pub fn inspect(&mut self, cpu: &Cpu) {
+        let Some(system) = self.slot_mut::<System>(0x0) else {
+            return;
+        };
+        // like the Console, a failed report shouldn't stop the ROM
+        let _ = system.print_debug(cpu);
+    }
+
+    /// Whether the ROM wrote a non-zero System/state, asking to stop
+    pub fn halted(&self) -> bool {
+        self.io[0x0f] != 0
+    }
+
+    /// The exit code the ROM asked for, System/state minus 0x80, once it
+    /// has halted
+    pub fn exit_code(&self) -> Option<u8> {
+        self.halted().then_some(self.io[0x0f] & 0x7f)
+    }
+
+    /// Run the vector of the device in `slot`, unless the ROM never set one
+    pub fn fire_vector(&mut self, cpu: &mut Cpu, slot: u8) -> Result<(), EmuError> {
+        let vector = read_short(&self.io, slot << 4);
+        if vector == 0 {
+            return Ok(());
+        }
+        self.eval(cpu, vector)
+    }
+
+    /// Run the vector an event belongs to
+    pub fn handle(&mut self, cpu: &mut Cpu, event: Event) -> Result<(), EmuError> {
+        match event {
+            Event::Frame => {
+                self.fire_vector(cpu, 0x2)?;
+                let mut sink = self.audio_sink.take();
+                let result = match sink.as_deref_mut() {
+                    Some(sink) => self.render_audio(cpu, FRAME_SAMPLES, sink),
+                    None => self.render_audio(cpu, FRAME_SAMPLES, &mut Vec::new()),
+                };
+                self.audio_sink = sink;
+                result
+            }
+            Event::Press(button) => self.controller_input(cpu, |c| c.press(button)),
+            Event::Release(button) => self.controller_input(cpu, |c| c.release(button)),
+            Event::Key(key) => {
+                // the key only reads as typed while its vector runs
+                self.controller_input(cpu, |c| c.type_key(key))?;
+                if let Some(controller) = self.slot_mut::<Controller>(0x8) {
+                    controller.type_key(0);
+                }
+                Ok(())
+            }
+            Event::MouseMove { x, y } => self.mouse_input(cpu, |m| m.move_to(x, y)),
+            Event::MousePress(button) => self.mouse_input(cpu, |m| m.press(button)),
+            Event::MouseRelease(button) => self.mouse_input(cpu, |m| m.release(button)),
+            Event::MouseScroll { x, y } => {
+                // like the key, scrolling only reads as non-zero during its
+                // vector
+                self.mouse_input(cpu, |m| m.scroll(x, y))?;
+                if let Some(mouse) = self.slot_mut::<Mouse>(0x9) {
+                    mouse.scroll(0, 0);
+                }
+                Ok(())
+            }
+            Event::Console(byte, kind) => {
+                self.io[0x12] = byte;
+                self.io[0x17] = kind as u8;
+                self.fire_vector(cpu, 0x1)
+            }
+        }
+    }
+
+    /// Pass input to the Controller and run its vector. Nothing happens if
+    /// slot 8 is empty or holds some other device.
+    fn controller_input(
+        &mut self,
+        cpu: &mut Cpu,
+        input: impl FnOnce(&mut Controller),
+    ) -> Result<(), EmuError> {
+        let Some(controller) = self.slot_mut::<Controller>(0x8) else {
+            return Ok(());
+        };
+        input(controller);
+        self.fire_vector(cpu, 0x8)
+    }
+
+    /// Pass input to the Mouse and run its vector, if slot 9 holds one
+    fn mouse_input(
+        &mut self,
+        cpu: &mut Cpu,
+        input: impl FnOnce(&mut Mouse),
+    ) -> Result<(), EmuError> {
+        let Some(mouse) = self.slot_mut::<Mouse>(0x9) else {
+            return Ok(());
+        };
+        input(mouse);
+        self.fire_vector(cpu, 0x9)
+    }
+
+    /// Feed every byte of `input` to the Console vector as stdin, blocking
+    /// until it runs out, then mark the end
+    pub fn stream_console(&mut self, cpu: &mut Cpu, input: impl Read) -> Result<(), EmuError> {
+        for byte in BufReader::new(input).bytes() {
+            let Ok(byte) = byte else { break };
+            if self.halted() {
+                return Ok(());
+            }
+            self.handle(cpu, Event::Console(byte, InputType::Stdin))?;
         }
+        self.handle(cpu, Event::Console(0, InputType::ArgumentEnd))
     }
 
-    pub fn deo(&mut self, addr: u8, byte: u8) {
+    /// Run the reset vector at 0x0100, then hand the ROM any arguments
+    /// queued on the Console. While the reset vector runs, Console/type is
+    /// non-zero if there are arguments coming.
+    pub fn reset(&mut self, cpu: &mut Cpu) -> Result<(), EmuError> {
+        let has_args = self
+            .device::<Console>()
+            .is_some_and(|console| console.has_queued_input());
+        self.io[0x17] = has_args as u8;
+        self.eval(cpu, 0x0100)?;
+        while let Some((byte, kind)) = self
+            .device_mut::<Console>()
+            .and_then(|console| console.next_input())
+        {
+            self.handle(cpu, Event::Console(byte, kind))?;
+        }
+        Ok(())
+    }
+
+    /// Send the audio of every frame from now on to `sink`
+    pub fn set_audio_sink(&mut self, sink: Box<dyn AudioSink>) -> Option<Box<dyn AudioSink>> {
+        self.audio_sink.replace(sink)
+    }
+
+    /// Mix `frames` stereo sample frames from the Audio devices into
+    /// `sink`, running the vector of each one whose note ended
+    pub fn render_audio(
+        &mut self,
+        cpu: &mut Cpu,
+        frames: usize,
+        sink: &mut dyn AudioSink,
+    ) -> Result<(), EmuError> {
+        let mut samples = vec![0; frames * 2];
+        let mut finished = vec![];
+        for slot in 0x3..=0x6 {
+            if let Some(audio) = self.slot_mut::<Audio>(slot) {
+                if audio.render(&mut samples) {
+                    finished.push(slot);
+                }
+            }
+        }
+        // like the Console, a sink that can't keep up shouldn't stop the ROM
+        let _ = sink.write(&samples);
+        for slot in finished {
+            self.fire_vector(cpu, slot)?;
+        }
+        Ok(())
+    }
+
+    /// Run a loaded ROM: the reset vector at 0x0100, then device vectors as
+    /// events come in, until the frontend closes or the ROM halts
+    pub fn run(&mut self, cpu: &mut Cpu, frontend: &mut impl Frontend) -> Result<(), EmuError> {
+        self.reset(cpu)?;
+        while frontend.is_open() && !self.halted() {
+            for event in frontend.poll() {
+                self.handle(cpu, event)?;
+            }
+            frontend.update(self);
+        }
+        Ok(())
+    }
+
+    pub fn deo(&mut self, addr: u8, byte: u8) -> Result<(), EmuError> {
         self.io[addr as usize] = byte;
-        match addr {
-            0x00..0x10 => self.system.notify_deo(&self.io, &self.main, addr, byte),
-            0x10..0x20 => self.console.notify_deo(&self.io, &self.main, addr, byte),
-            0x20..0x30 => self.screen.notify_deo(&self.io, &self.main, addr, byte),
-            _ => todo!(),
+        match &mut self.devices[(addr >> 4) as usize] {
+            Some(device) => device.deo(&mut self.io, &mut self.main, addr, byte),
+            // like reads, writes to an empty slot just land in io
+            None => Ok(()),
         }
     }
 
-    pub fn deo2(&mut self, addr: u8, short: u16) {
+    pub fn deo2(&mut self, addr: u8, short: u16) -> Result<(), EmuError> {
+        // erroring if 0x_F because writing a short to that address would
+        // mean writing half to one device and half to another
+        if addr & 0x0F == 0x0F {
+            return Err(EmuError::UnknownPort(addr));
+        }
         write_short(&mut self.io, addr, short);
-        #[allow(non_contiguous_range_endpoints)]
-        match addr {
-            // panicking if 0x_F because writing a short to that address would
-            // mean writing half to one device and half to another
-            0x00..0x0F => self.system.notify_deo2(&self.io, &self.main, addr, short),
-            0x10..0x1F => self.console.notify_deo2(&self.io, &self.main, addr, short),
-            0x20..0x2F => self.screen.notify_deo2(&self.io, &self.main, addr, short),
-            _ => todo!(),
+        match &mut self.devices[(addr >> 4) as usize] {
+            Some(device) => device.deo2(&mut self.io, &mut self.main, addr, short),
+            None => Ok(()),
         }
     }
 
-    pub fn dei(&self, addr: u8) -> u8 {
-        self.io[addr as usize]
+    pub fn dei(&mut self, addr: u8) -> Result<u8, EmuError> {
+        match &mut self.devices[(addr >> 4) as usize] {
+            Some(device) => device.dei(&self.io, addr),
+            None => Ok(self.io[addr as usize]),
+        }
     }
 
-    pub fn dei2(&self, addr: u8) -> u16 {
-        read_short(&self.io, addr)
+    pub fn dei2(&mut self, addr: u8) -> Result<u16, EmuError> {
+        match &mut self.devices[(addr >> 4) as usize] {
+            Some(device) => device.dei2(&self.io, addr),
+            None => Ok(read_short(&self.io, addr)),
+        }
     }
 
-    pub fn update_window(&mut self) {
-        let rgb_buffer: Vec<_> = self
-            .screen
-            .buffer
-            .iter()
-            .map(|&i| self.system.index_to_0rgb(i))
-            .collect();
-        self.window
-            .update_with_buffer(&rgb_buffer, WIDTH, HEIGHT)
-            .unwrap();
+    /// Width and height of the Screen, which ROMs can change
+    pub fn screen_size(&self) -> (usize, usize) {
+        match self.device::<Screen>() {
+            Some(screen) => (screen.width(), screen.height()),
+            None => (DEFAULT_WIDTH, DEFAULT_HEIGHT),
+        }
+    }
+
+    /// The framebuffer as 0RGB pixels, ready for a frontend to show
+    pub fn rgb_buffer(&self) -> Vec<u32> {
+        match (self.device::<Screen>(), self.device::<System>()) {
+            (Some(screen), Some(system)) => screen
+                .buffer
+                .iter()
+                .map(|&i| system.index_to_0rgb(i))
+                .collect(),
+            _ => vec![],
+        }
     }
 }
 
@@ -120,8 +395,418 @@ pub fn read_short(mem: &[u8], addr: u8) -> u16 {
     u16::from_be_bytes([high, low])
 }
 
-pub fn read_bytes(mem: &[u8], addr: u16, num_bytes: u8) -> &[u8] {
-    let addr = addr as usize;
-    let num_bytes = num_bytes as usize;
-    &mem[addr..addr + num_bytes]
+/// Write a short to main memory, wrapping around past 0xFFFF
+pub fn write_main_short(mem: &mut [u8; 0x10000], addr: u16, short: u16) {
+    let [high, low] = short.to_be_bytes();
+    mem[addr as usize] = high;
+    mem[addr.wrapping_add(1) as usize] = low;
+}
+
+/// Read a short from main memory, wrapping around past 0xFFFF
+pub fn read_main_short(mem: &[u8; 0x10000], addr: u16) -> u16 {
+    let high = mem[addr as usize];
+    let low = mem[addr.wrapping_add(1) as usize];
+    u16::from_be_bytes([high, low])
+}
+
+/// Read bytes from main memory, wrapping around past 0xFFFF
+pub fn read_bytes<const N: usize>(mem: &[u8], addr: u16) -> [u8; N] {
+    std::array::from_fn(|i| mem[addr.wrapping_add(i as u16) as usize])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::controller::Button;
+    use crate::frontend::Script;
+    use crate::mouse::MouseButton;
+
+    /// Hands out a fixed number of frames, then closes
+    struct Frames {
+        left: usize,
+        updates: usize,
+    }
+
+    impl Frontend for Frames {
+        fn is_open(&self) -> bool {
+            self.left > 0
+        }
+
+        fn poll(&mut self) -> Vec<Event> {
+            self.left -= 1;
+            vec![Event::Frame]
+        }
+
+        fn update(&mut self, _varvara: &Varvara) {
+            self.updates += 1;
+        }
+    }
+
+    #[test]
+    fn screen_vector_runs_every_frame() {
+        let mut varvara = Varvara::new();
+        let mut cpu = Cpu::new();
+        // ;on-frame .Screen/vector DEO2 BRK
+        // @on-frame #00 LDZ INC #00 STZ BRK
+        let rom = [
+            0xa0, 0x01, 0x07, 0x80, 0x20, 0x37, 0x00, 0x80, 0x00, 0x10, 0x01, 0x80, 0x00, 0x11,
+            0x00,
+        ];
+        varvara.main[0x0100..0x0100 + rom.len()].copy_from_slice(&rom);
+        let mut frontend = Frames {
+            left: 3,
+            updates: 0,
+        };
+
+        varvara.run(&mut cpu, &mut frontend).unwrap();
+
+        assert_eq!(varvara.main[0x00], 3);
+        assert_eq!(frontend.updates, 3);
+    }
+
+    #[test]
+    fn state_port_halts_with_exit_code() {
+        let mut varvara = Varvara::new();
+        let mut cpu = Cpu::new();
+        // ;on-frame .Screen/vector DEO2 BRK
+        // @on-frame #00 LDZ INC DUP #00 STZ #02 EQU ?{ BRK }
+        //     #82 .System/state DEO #01 #01 STZ BRK
+        let rom = [
+            0xa0, 0x01, 0x07, 0x80, 0x20, 0x37, 0x00, 0x80, 0x00, 0x10, 0x01, 0x06, 0x80, 0x00,
+            0x11, 0x80, 0x02, 0x08, 0x20, 0x00, 0x01, 0x00, 0x80, 0x82, 0x80, 0x0f, 0x17, 0x80,
+            0x01, 0x80, 0x01, 0x11, 0x00,
+        ];
+        varvara.main[0x0100..0x0100 + rom.len()].copy_from_slice(&rom);
+        let mut frontend = Frames {
+            left: 5,
+            updates: 0,
+        };
+        assert_eq!(varvara.exit_code(), None);
+
+        varvara.run(&mut cpu, &mut frontend).unwrap();
+
+        assert_eq!(varvara.main[0x00..0x02], [2, 0]);
+        assert_eq!(frontend.left, 3);
+        assert_eq!(varvara.exit_code(), Some(2));
+    }
+
+    #[test]
+    fn controller_vector_sees_buttons_and_keys() {
+        let mut varvara = Varvara::new();
+        let mut cpu = Cpu::new();
+        // ;on-button .Controller/vector DEO2 BRK
+        // @on-button .Controller/button DEI .Controller/key DEI ADD #00 STZ BRK
+        let rom = [
+            0xa0, 0x01, 0x07, 0x80, 0x80, 0x37, 0x00, 0x80, 0x82, 0x16, 0x80, 0x83, 0x16, 0x18,
+            0x80, 0x00, 0x11, 0x00,
+        ];
+        varvara.main[0x0100..0x0100 + rom.len()].copy_from_slice(&rom);
+        let mut script = Script::new(vec![
+            vec![Event::Press(Button::A), Event::Press(Button::Up)],
+            vec![Event::Key(b'a')],
+        ]);
+
+        varvara.eval(&mut cpu, 0x0100).unwrap();
+        for event in script.poll() {
+            varvara.handle(&mut cpu, event).unwrap();
+        }
+        assert_eq!(varvara.main[0x00], 0x11);
+
+        for event in script.poll() {
+            varvara.handle(&mut cpu, event).unwrap();
+        }
+        assert_eq!(varvara.main[0x00], 0x11 + b'a');
+        assert_eq!(varvara.dei(0x83), Ok(0));
+
+        varvara.handle(&mut cpu, Event::Release(Button::A)).unwrap();
+        assert_eq!(varvara.dei(0x82), Ok(0x10));
+    }
+
+    #[test]
+    fn controller_events_need_a_controller_in_slot_8() {
+        let mut varvara = Varvara::new();
+        let mut cpu = Cpu::new();
+        // ;on-button .Controller/vector DEO2 BRK @on-button #01 #00 STZ BRK
+        let rom = [
+            0xa0, 0x01, 0x07, 0x80, 0x80, 0x37, 0x00, 0x80, 0x01, 0x80, 0x00, 0x11, 0x00,
+        ];
+        varvara.main[0x0100..0x0100 + rom.len()].copy_from_slice(&rom);
+        varvara.eval(&mut cpu, 0x0100).unwrap();
+
+        varvara.register(0x8, Box::new(Counter::default()));
+        varvara.handle(&mut cpu, Event::Press(Button::A)).unwrap();
+        assert_eq!(varvara.main[0x00], 0x00);
+
+        varvara.unregister(0x8);
+        varvara.handle(&mut cpu, Event::Key(b'a')).unwrap();
+        assert_eq!(varvara.main[0x00], 0x00);
+    }
+
+    #[test]
+    fn mouse_vector_sees_position_buttons_and_scroll() {
+        let mut varvara = Varvara::new();
+        let mut cpu = Cpu::new();
+        // ;on-mouse .Mouse/vector DEO2 BRK
+        // @on-mouse .Mouse/x DEI2 #00 STZ2 .Mouse/state DEI #02 STZ
+        //     .Mouse/scrolly DEI2 #03 STZ2 BRK
+        let rom = [
+            0xa0, 0x01, 0x07, 0x80, 0x90, 0x37, 0x00, 0x80, 0x92, 0x36, 0x80, 0x00, 0x31, 0x80,
+            0x96, 0x16, 0x80, 0x02, 0x11, 0x80, 0x9c, 0x36, 0x80, 0x03, 0x31, 0x00,
+        ];
+        varvara.main[0x0100..0x0100 + rom.len()].copy_from_slice(&rom);
+        varvara.eval(&mut cpu, 0x0100).unwrap();
+
+        varvara
+            .handle(
+                &mut cpu,
+                Event::MouseMove {
+                    x: 0x0123,
+                    y: 0x0045,
+                },
+            )
+            .unwrap();
+        varvara
+            .handle(&mut cpu, Event::MousePress(MouseButton::Right))
+            .unwrap();
+        assert_eq!(varvara.main[0x00..0x03], [0x01, 0x23, 0x04]);
+        assert_eq!(varvara.dei2(0x94), Ok(0x0045));
+
+        varvara
+            .handle(&mut cpu, Event::MouseScroll { x: 0, y: -1 })
+            .unwrap();
+        assert_eq!(varvara.main[0x03..0x05], [0xff, 0xff]);
+        assert_eq!(varvara.dei2(0x9c), Ok(0x0000));
+
+        varvara
+            .handle(&mut cpu, Event::MouseRelease(MouseButton::Right))
+            .unwrap();
+        assert_eq!(varvara.dei(0x96), Ok(0x00));
+    }
+
+    #[test]
+    fn mouse_events_need_a_mouse_in_slot_9() {
+        let mut varvara = Varvara::new();
+        let mut cpu = Cpu::new();
+        // ;on-mouse .Mouse/vector DEO2 BRK @on-mouse #01 #00 STZ BRK
+        let rom = [
+            0xa0, 0x01, 0x07, 0x80, 0x90, 0x37, 0x00, 0x80, 0x01, 0x80, 0x00, 0x11, 0x00,
+        ];
+        varvara.main[0x0100..0x0100 + rom.len()].copy_from_slice(&rom);
+        varvara.eval(&mut cpu, 0x0100).unwrap();
+
+        varvara.register(0x9, Box::new(Counter::default()));
+        varvara
+            .handle(&mut cpu, Event::MouseMove { x: 1, y: 1 })
+            .unwrap();
+        assert_eq!(varvara.main[0x00], 0x00);
+
+        varvara.unregister(0x9);
+        varvara
+            .handle(&mut cpu, Event::MousePress(MouseButton::Left))
+            .unwrap();
+        assert_eq!(varvara.main[0x00], 0x00);
+    }
+
+    #[test]
+    fn console_vector_sees_stdin() {
+        let mut varvara = Varvara::new();
+        let mut cpu = Cpu::new();
+        // ;on-console .Console/vector DEO2 BRK
+        // @on-console .Console/read DEI #00 LDZ STZ #00 LDZ INC #00 STZ BRK
+        let rom = [
+            0xa0, 0x01, 0x07, 0x80, 0x10, 0x37, 0x00, 0x80, 0x12, 0x16, 0x80, 0x00, 0x10, 0x11,
+            0x80, 0x00, 0x10, 0x01, 0x80, 0x00, 0x11, 0x00,
+        ];
+        varvara.main[0x0100..0x0100 + rom.len()].copy_from_slice(&rom);
+        varvara.main[0x00] = 0x10;
+        varvara.eval(&mut cpu, 0x0100).unwrap();
+
+        varvara.stream_console(&mut cpu, &b"hi"[..]).unwrap();
+        assert_eq!(&varvara.main[0x10..0x13], b"hi\0");
+        assert_eq!(varvara.main[0x00], 0x13);
+        assert_eq!(varvara.io[0x17], InputType::ArgumentEnd as u8);
+    }
+
+    #[test]
+    fn console_vector_sees_args() {
+        let mut varvara = Varvara::new();
+        let mut cpu = Cpu::new();
+        // .Console/type DEI #01 STZ ;on-console .Console/vector DEO2 BRK
+        // @on-console .Console/read DEI #00 LDZ STZ
+        //     .Console/type DEI #00 LDZ #10 ADD STZ #00 LDZ INC #00 STZ BRK
+        let rom = [
+            0x80, 0x17, 0x16, 0x80, 0x01, 0x11, 0xa0, 0x01, 0x0d, 0x80, 0x10, 0x37, 0x00, 0x80,
+            0x12, 0x16, 0x80, 0x00, 0x10, 0x11, 0x80, 0x17, 0x16, 0x80, 0x00, 0x10, 0x80, 0x10,
+            0x18, 0x11, 0x80, 0x00, 0x10, 0x01, 0x80, 0x00, 0x11, 0x00,
+        ];
+        varvara.main[0x0100..0x0100 + rom.len()].copy_from_slice(&rom);
+        varvara.main[0x00] = 0x20;
+        varvara
+            .device_mut::<Console>()
+            .unwrap()
+            .queue_args(["ab", "c"]);
+        varvara.reset(&mut cpu).unwrap();
+
+        assert_eq!(varvara.main[0x01], 0x01);
+        assert_eq!(&varvara.main[0x20..0x25], b"ab\nc\n");
+        assert_eq!(varvara.main[0x30..0x35], [2, 2, 3, 2, 4]);
+    }
+
+    #[test]
+    fn expansion_fills_and_copies_across_pages() {
+        let mut varvara = Varvara::new();
+        // fill 4 bytes of page 1 at 0xfffe with 0xaa, wrapping to the start
+        // of the page rather than into page 2
+        varvara.main[0x0200..0x0208]
+            .copy_from_slice(&[0x00, 0x00, 0x04, 0x00, 0x01, 0xff, 0xfe, 0xaa]);
+        varvara.deo2(0x02, 0x0200).unwrap();
+        let system = varvara.device::<System>().unwrap();
+        let page = system.bank(1).unwrap();
+        assert_eq!(page[0xfffe..], [0xaa; 2]);
+        assert_eq!(page[..3], [0xaa, 0xaa, 0x00]);
+        assert_eq!(system.bank(2), None);
+
+        // cpyl those bytes back into main memory
+        varvara.main[0x0300..0x030b].copy_from_slice(&[
+            0x01, 0x00, 0x04, 0x00, 0x01, 0xff, 0xfe, 0x00, 0x00, 0x10, 0x00,
+        ]);
+        varvara.deo2(0x02, 0x0300).unwrap();
+        assert_eq!(varvara.main[0x1000..0x1004], [0xaa; 4]);
+
+        // cpyr shifts overlapping bytes up without smearing them
+        varvara.main[0x2000..0x2004].copy_from_slice(&[1, 2, 3, 4]);
+        varvara.main[0x0400..0x040b].copy_from_slice(&[
+            0x02, 0x00, 0x04, 0x00, 0x00, 0x20, 0x00, 0x00, 0x00, 0x20, 0x01,
+        ]);
+        varvara.deo2(0x02, 0x0400).unwrap();
+        assert_eq!(varvara.main[0x2000..0x2005], [1, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn expansion_wraps_to_allocated_pages() {
+        let mut varvara = Varvara::new();
+        varvara.set_pages(2);
+        assert_eq!(varvara.pages(), 2);
+        // page 3 of 2 is page 1
+        varvara.main[0x0200..0x0208]
+            .copy_from_slice(&[0x00, 0x00, 0x02, 0x00, 0x03, 0x00, 0x10, 0x55]);
+        varvara.deo2(0x02, 0x0200).unwrap();
+        assert_eq!(
+            varvara.device::<System>().unwrap().bank(1).unwrap()[0x10..0x12],
+            [0x55; 2]
+        );
+    }
+
+    #[test]
+    fn metadata_port_registers_block() {
+        let mut varvara = Varvara::new();
+        let mut cpu = Cpu::new();
+        assert_eq!(varvara.metadata(), None);
+        // ;meta .System/metadata DEO2 BRK @meta 00 "Hi 00
+        let rom = [
+            0xa0, 0x01, 0x07, 0x80, 0x06, 0x37, 0x00, 0x00, b'H', b'i', 0x00,
+        ];
+        varvara.main[0x0100..0x0100 + rom.len()].copy_from_slice(&rom);
+        varvara.eval(&mut cpu, 0x0100).unwrap();
+
+        let metadata = varvara.metadata().unwrap();
+        assert_eq!(metadata.name(), "Hi");
+        assert_eq!(Metadata::from_rom(&rom), Some(metadata));
+    }
+
+    #[test]
+    fn unset_vector_does_nothing() {
+        let mut varvara = Varvara::new();
+        let mut cpu = Cpu::new();
+
+        varvara.fire_vector(&mut cpu, 0x2).unwrap();
+
+        assert_eq!(cpu.counter, 0x0100);
+    }
+
+    /// Counts reads of its ports and remembers the last byte written
+    #[derive(Default)]
+    struct Counter {
+        reads: u8,
+        last: Option<(u8, u8)>,
+    }
+
+    impl Device for Counter {
+        fn dei(&mut self, _io: &[u8], _addr: u8) -> Result<u8, EmuError> {
+            self.reads += 1;
+            Ok(self.reads)
+        }
+
+        fn deo(
+            &mut self,
+            _io: &mut [u8],
+            _main: &mut [u8],
+            addr: u8,
+            byte: u8,
+        ) -> Result<(), EmuError> {
+            self.last = Some((addr, byte));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn registered_device_gets_reads_and_writes() {
+        let mut varvara = Varvara::new();
+        varvara.register(0xe, Box::new(Counter::default()));
+
+        assert_eq!(varvara.dei(0xe0), Ok(1));
+        assert_eq!(varvara.dei(0xe4), Ok(2));
+        varvara.deo(0xe3, 0x42).unwrap();
+
+        let counter = varvara.device::<Counter>().unwrap();
+        assert_eq!(counter.last, Some((0xe3, 0x42)));
+        assert_eq!(varvara.io[0xe3], 0x42);
+    }
+
+    #[test]
+    fn empty_slot_ignores_writes() {
+        let mut varvara = Varvara::new();
+
+        assert_eq!(varvara.deo(0xe0, 0x01), Ok(()));
+        assert_eq!(varvara.deo2(0xe2, 0x0203), Ok(()));
+        assert_eq!(varvara.dei(0xe0), Ok(0x01));
+        assert_eq!(varvara.dei2(0xe2), Ok(0x0203));
+    }
+
+    #[test]
+    fn unregistered_device_is_gone() {
+        let mut varvara = Varvara::new();
+        varvara.unregister(0x2);
+
+        assert!(varvara.device::<Screen>().is_none());
+        assert!(varvara.rgb_buffer().is_empty());
+    }
+
+    #[test]
+    fn main_short_wraps() {
+        let mut mem = [0; 0x10000];
+        write_main_short(&mut mem, 0xffff, 0x1234);
+
+        assert_eq!(mem[0xffff], 0x12);
+        assert_eq!(mem[0x0000], 0x34);
+        assert_eq!(read_main_short(&mem, 0xffff), 0x1234);
+    }
+
+    #[test]
+    fn io_short_wraps() {
+        let mut io = [0; 0x100];
+        write_short(&mut io, 0xff, 0xabcd);
+
+        assert_eq!(io[0xff], 0xab);
+        assert_eq!(io[0x00], 0xcd);
+        assert_eq!(read_short(&io, 0xff), 0xabcd);
+    }
+
+    #[test]
+    fn last_byte_of_main_is_addressable() {
+        let mut mem = [0; 0x10000];
+        mem[0xffff] = 0x80;
+
+        assert_eq!(read_main_short(&mem, 0xfffe), 0x0080);
+    }
 }
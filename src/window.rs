@@ -0,0 +1,232 @@
+use std::cell::RefCell;
+use std::io;
+use std::rc::Rc;
+use std::sync::mpsc::{Receiver, TryRecvError};
+
+use minifb::{InputCallback, Key, KeyRepeat, MouseMode, Scale, WindowOptions};
+
+use super::console::{self, InputType};
+use super::controller::Button;
+use super::frontend::{Event, Frontend};
+use super::mouse::MouseButton;
+use super::screen::{DEFAULT_HEIGHT, DEFAULT_WIDTH};
+use super::varvara::Varvara;
+
+/// How many window pixels make up one screen pixel
+const SCALE: usize = 4;
+
+const MOUSE_BUTTONS: [(minifb::MouseButton, MouseButton); 3] = [
+    (minifb::MouseButton::Left, MouseButton::Left),
+    (minifb::MouseButton::Middle, MouseButton::Middle),
+    (minifb::MouseButton::Right, MouseButton::Right),
+];
+
+/// A minifb window showing the screen, closed with ESC
+pub struct Window {
+    window: minifb::Window,
+    typed: Rc<RefCell<Vec<u32>>>,
+    /// Mouse position in screen pixels as of the last poll
+    mouse: Option<(u16, u16)>,
+    /// Which of `MOUSE_BUTTONS` were down as of the last poll
+    mouse_down: [bool; 3],
+    /// Bytes read from stdin in the background, until it ends
+    stdin: Option<Receiver<u8>>,
+    /// Whether the title shows the ROM's name yet
+    titled: bool,
+    title: String,
+    /// Screen size the window was opened for
+    size: (usize, usize),
+    /// Whether drawing to the window failed, which closes it
+    failed: bool,
+}
+
+/// Collects characters typed into the window until the next poll
+struct Typed(Rc<RefCell<Vec<u32>>>);
+
+impl InputCallback for Typed {
+    fn add_char(&mut self, uni_char: u32) {
+        self.0.borrow_mut().push(uni_char);
+    }
+}
+
+/// Controller button a key stands in for
+fn key_to_button(key: Key) -> Option<Button> {
+    match key {
+        Key::LeftCtrl | Key::RightCtrl => Some(Button::A),
+        Key::LeftAlt | Key::RightAlt => Some(Button::B),
+        Key::LeftShift | Key::RightShift => Some(Button::Select),
+        Key::Home => Some(Button::Start),
+        Key::Up => Some(Button::Up),
+        Key::Down => Some(Button::Down),
+        Key::Left => Some(Button::Left),
+        Key::Right => Some(Button::Right),
+        _ => None,
+    }
+}
+
+/// Control characters the input callback doesn't report
+fn key_to_control(key: Key) -> Option<u8> {
+    match key {
+        Key::Backspace => Some(0x08),
+        Key::Tab => Some(0x09),
+        Key::Enter | Key::NumPadEnter => Some(0x0d),
+        Key::Delete => Some(0x7f),
+        _ => None,
+    }
+}
+
+/// Open a window fitting a screen of `size`. minifb can't resize a window
+/// from code, so a new size means a new window.
+fn open(
+    title: &str,
+    size: (usize, usize),
+    typed: &Rc<RefCell<Vec<u32>>>,
+) -> minifb::Result<minifb::Window> {
+    let (width, height) = size;
+    let mut window = minifb::Window::new(
+        title,
+        width,
+        height,
+        WindowOptions {
+            scale: Scale::X4,
+            ..WindowOptions::default()
+        },
+    )?;
+    window.set_target_fps(60);
+    window.set_input_callback(Box::new(Typed(typed.clone())));
+    Ok(window)
+}
+
+impl Window {
+    /// Open a window for a screen of the default size
+    pub fn new() -> minifb::Result<Self> {
+        let title = "Test - ESC to exit".to_string();
+        let size = (DEFAULT_WIDTH, DEFAULT_HEIGHT);
+        let typed = Rc::new(RefCell::new(vec![]));
+        let window = open(&title, size, &typed)?;
+        Ok(Self {
+            window,
+            typed,
+            mouse: None,
+            mouse_down: [false; 3],
+            stdin: Some(console::spawn_reader(io::stdin())),
+            titled: false,
+            title,
+            size,
+            failed: false,
+        })
+    }
+
+    /// Whether the window closed because drawing to it failed
+    pub fn failed(&self) -> bool {
+        self.failed
+    }
+}
+
+impl Window {
+    fn poll_stdin(&mut self, events: &mut Vec<Event>) {
+        let Some(stdin) = &self.stdin else { return };
+        loop {
+            match stdin.try_recv() {
+                Ok(byte) => events.push(Event::Console(byte, InputType::Stdin)),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    events.push(Event::Console(0, InputType::ArgumentEnd));
+                    self.stdin = None;
+                    break;
+                }
+            }
+        }
+    }
+
+    fn poll_mouse(&mut self, events: &mut Vec<Event>) {
+        let position = self
+            .window
+            .get_unscaled_mouse_pos(MouseMode::Discard)
+            .map(|(x, y)| ((x as usize / SCALE) as u16, (y as usize / SCALE) as u16));
+        if let Some((x, y)) = position {
+            if self.mouse != position {
+                events.push(Event::MouseMove { x, y });
+            }
+            self.mouse = position;
+        }
+        for (i, (minifb_button, button)) in MOUSE_BUTTONS.iter().enumerate() {
+            let down = self.window.get_mouse_down(*minifb_button);
+            if down && !self.mouse_down[i] {
+                events.push(Event::MousePress(*button));
+            } else if !down && self.mouse_down[i] {
+                events.push(Event::MouseRelease(*button));
+            }
+            self.mouse_down[i] = down;
+        }
+        if let Some((x, y)) = self.window.get_scroll_wheel() {
+            // minifb scrolls up with positive y, Varvara with negative
+            events.push(Event::MouseScroll {
+                x: x.signum() as i16,
+                y: -y.signum() as i16,
+            });
+        }
+    }
+}
+
+impl Frontend for Window {
+    fn is_open(&self) -> bool {
+        !self.failed && self.window.is_open() && !self.window.is_key_down(Key::Escape)
+    }
+
+    fn poll(&mut self) -> Vec<Event> {
+        let mut events = vec![];
+        for key in self.window.get_keys_pressed(KeyRepeat::No) {
+            if let Some(button) = key_to_button(key) {
+                events.push(Event::Press(button));
+            }
+        }
+        for key in self.window.get_keys_released() {
+            if let Some(button) = key_to_button(key) {
+                events.push(Event::Release(button));
+            }
+        }
+        for key in self.window.get_keys_pressed(KeyRepeat::Yes) {
+            if let Some(control) = key_to_control(key) {
+                events.push(Event::Key(control));
+            }
+        }
+        for c in self.typed.borrow_mut().drain(..) {
+            if let Ok(c) = u8::try_from(c) {
+                events.push(Event::Key(c));
+            }
+        }
+        self.poll_mouse(&mut events);
+        self.poll_stdin(&mut events);
+        // update_with_buffer holds each frame to the target fps
+        events.push(Event::Frame);
+        events
+    }
+
+    fn update(&mut self, varvara: &Varvara) {
+        if !self.titled {
+            if let Some(metadata) = varvara.metadata() {
+                self.title = format!("{} - ESC to exit", metadata.name());
+                self.window.set_title(&self.title);
+                self.titled = true;
+            }
+        }
+        let size = varvara.screen_size();
+        if size != self.size {
+            // if there's no new window the old one shows the screen scaled
+            match open(&self.title, size, &self.typed) {
+                Ok(window) => self.window = window,
+                Err(error) => eprintln!("{error}"),
+            }
+            self.size = size;
+        }
+        let (width, height) = size;
+        let drawn = self
+            .window
+            .update_with_buffer(&varvara.rgb_buffer(), width, height);
+        if let Err(error) = drawn {
+            eprintln!("{error}");
+            self.failed = true;
+        }
+    }
+}